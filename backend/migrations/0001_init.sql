@@ -0,0 +1,18 @@
+-- Original schema. IF NOT EXISTS so DBs made before migrations existed adopt it.
+CREATE TABLE IF NOT EXISTS file (
+    id          TEXT PRIMARY KEY,
+    abs_path    TEXT NOT NULL UNIQUE,
+    name        TEXT NOT NULL,
+    size_bytes  INTEGER NOT NULL,
+    created_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE IF NOT EXISTS share (
+    slug            TEXT PRIMARY KEY,
+    file_id         TEXT NOT NULL REFERENCES file(id) ON DELETE CASCADE,
+    expires_at      TEXT,
+    max_downloads   INTEGER,
+    dl_count        INTEGER NOT NULL DEFAULT 0,
+    password_hash   TEXT,
+    created_at      TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,14 @@
+-- Admin accounts and login sessions
+CREATE TABLE IF NOT EXISTS admin (
+    id              INTEGER PRIMARY KEY,
+    username        TEXT NOT NULL UNIQUE,
+    password_hash   TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE IF NOT EXISTS admin_session (
+    token       TEXT PRIMARY KEY,
+    admin_id    INTEGER NOT NULL REFERENCES admin(id) ON DELETE CASCADE,
+    expires_at  TEXT NOT NULL,
+    created_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,21 @@
+-- Recorded mtime (unix seconds), used for ETag / If-Range
+ALTER TABLE file ADD COLUMN mtime INTEGER;
+
+-- A client's logical download. Ranged / resumed requests carrying the
+-- session id aren't counted against max_downloads again.
+CREATE TABLE download_session (
+    id          TEXT PRIMARY KEY,
+    slug        TEXT NOT NULL REFERENCES share(slug) ON DELETE CASCADE,
+    expires_at  TEXT NOT NULL,
+    created_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- Bytes a session has been sent of each file, so resuming can't hand out
+-- more than one copy. Bytes are reserved when a request starts and the
+-- unsent part handed back when it ends.
+CREATE TABLE download_progress (
+    session     TEXT NOT NULL REFERENCES download_session(id) ON DELETE CASCADE,
+    path        TEXT NOT NULL,
+    bytes       INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (session, path)
+);
@@ -0,0 +1,6 @@
+-- SHA-256 of the file content (lowercase hex), NULL until hashed
+ALTER TABLE file ADD COLUMN sha256 TEXT;
+ALTER TABLE file ADD COLUMN hashed_at TEXT;
+-- 'pending' while a background hash is queued or running, 'failed' if it
+-- couldn't read the file, NULL otherwise
+ALTER TABLE file ADD COLUMN hash_state TEXT;
@@ -0,0 +1,11 @@
+-- Chunked upload in progress. The bytes live in the storage dir's
+-- partial dir under the session id; `received_bytes` is only advanced
+-- after they were synced to disk.
+CREATE TABLE upload_session (
+    id              TEXT PRIMARY KEY,
+    file_name       TEXT NOT NULL,
+    size_bytes      INTEGER NOT NULL,
+    received_bytes  INTEGER NOT NULL DEFAULT 0,
+    expires_at      TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,5 @@
+-- 'file' or 'dir'. Directories are served as generated archives, their
+-- size_bytes is the total of the files below and entry_count the number
+-- of files and subdirectories, both as of the last scan.
+ALTER TABLE file ADD COLUMN kind TEXT NOT NULL DEFAULT 'file';
+ALTER TABLE file ADD COLUMN entry_count INTEGER;
@@ -0,0 +1,12 @@
+-- Files a share hands out, in order. share.file_id stays the first one;
+-- shares with more than one row here are bundles.
+CREATE TABLE share_file (
+    slug        TEXT NOT NULL REFERENCES share(slug) ON DELETE CASCADE,
+    file_id     TEXT NOT NULL REFERENCES file(id) ON DELETE CASCADE,
+    position    INTEGER NOT NULL,
+    PRIMARY KEY (slug, file_id)
+);
+CREATE INDEX share_file_file ON share_file(file_id);
+
+INSERT INTO share_file (slug, file_id, position)
+SELECT slug, file_id, 0 FROM share;
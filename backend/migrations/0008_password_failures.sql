@@ -0,0 +1,11 @@
+-- Failed share password attempts, counted per slug and per client IP
+-- so a lockout outlives a restart. A counter starts over once a day
+-- passed since its last failure.
+CREATE TABLE password_failure (
+    scope           TEXT NOT NULL CHECK (scope IN ('slug', 'ip')),
+    key             TEXT NOT NULL,
+    failures        INTEGER NOT NULL,
+    locked_until    TEXT,
+    last_failed_at  TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (scope, key)
+);
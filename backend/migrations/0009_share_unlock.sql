@@ -0,0 +1,8 @@
+-- Proof that a client gave a share's password, handed out by the unlock
+-- endpoint so the password itself never has to travel in a URL
+CREATE TABLE share_unlock (
+    token       TEXT PRIMARY KEY,
+    slug        TEXT NOT NULL REFERENCES share(slug) ON DELETE CASCADE,
+    expires_at  TEXT NOT NULL,
+    created_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,8 @@
+-- HMAC keys for signed download URLs. The newest key signs, every key
+-- still in the table verifies, so rotating doesn't break URLs already
+-- handed out until the old key is deleted.
+CREATE TABLE signing_key (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    secret      BLOB NOT NULL,
+    created_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
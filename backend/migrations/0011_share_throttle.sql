@@ -0,0 +1,3 @@
+-- Per-share overrides of the download limits, NULL uses the server default
+ALTER TABLE share ADD COLUMN max_concurrent INTEGER;
+ALTER TABLE share ADD COLUMN bytes_per_sec INTEGER;
@@ -0,0 +1,14 @@
+-- What the janitor keeps of shares it retires, slugs can be reused after
+CREATE TABLE share_archive (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug            TEXT NOT NULL,
+    abs_path        TEXT NOT NULL,
+    file_count      INTEGER NOT NULL,
+    reason          TEXT NOT NULL CHECK (reason IN ('expired', 'exhausted')),
+    expires_at      TEXT,
+    max_downloads   INTEGER,
+    dl_count        INTEGER NOT NULL,
+    created_at      TEXT NOT NULL,
+    archived_at     TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX share_archive_slug ON share_archive(slug);
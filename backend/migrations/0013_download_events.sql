@@ -0,0 +1,15 @@
+-- One row per download request, written when the response is done
+CREATE TABLE download_event (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug        TEXT NOT NULL,
+    file_id     TEXT,
+    at          TEXT NOT NULL DEFAULT (datetime('now')),
+    ip          TEXT,
+    user_agent  TEXT,
+    bytes_sent  INTEGER NOT NULL DEFAULT 0,
+    range       TEXT,
+    outcome     TEXT NOT NULL
+);
+CREATE INDEX download_event_slug ON download_event(slug, at);
+CREATE INDEX download_event_file ON download_event(file_id, at);
+CREATE INDEX download_event_at ON download_event(at);
@@ -0,0 +1,24 @@
+-- Every mutating admin call, secrets in params are redacted
+CREATE TABLE admin_audit (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    at          TEXT NOT NULL DEFAULT (datetime('now')),
+    actor       TEXT,
+    action      TEXT NOT NULL,
+    target      TEXT,
+    params      TEXT,
+    ip          TEXT,
+    status      INTEGER NOT NULL
+);
+CREATE INDEX admin_audit_at ON admin_audit(at);
+CREATE INDEX admin_audit_actor ON admin_audit(actor, at);
+CREATE INDEX admin_audit_target ON admin_audit(target, at);
+
+-- Append-only
+CREATE TRIGGER admin_audit_no_update BEFORE UPDATE ON admin_audit
+BEGIN
+    SELECT RAISE(ABORT, 'admin_audit is append-only');
+END;
+CREATE TRIGGER admin_audit_no_delete BEFORE DELETE ON admin_audit
+BEGIN
+    SELECT RAISE(ABORT, 'admin_audit is append-only');
+END;
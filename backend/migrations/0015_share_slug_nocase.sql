@@ -0,0 +1,4 @@
+-- Custom slugs are unique regardless of case, so `Q3-report` and `q3-report`
+-- can't both be handed out. Not a unique index: generated alphanumeric slugs
+-- from before this may already differ only in case.
+CREATE INDEX share_slug_nocase ON share(slug COLLATE NOCASE);
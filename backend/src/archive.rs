@@ -0,0 +1,701 @@
+// src/archive.rs
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+use crate::policy::PathPolicy;
+
+/// Size of the chunks handed to the response body
+const CHUNK: usize = 64 * 1024;
+/// Chunks buffered between the archive thread and the response, together
+/// with `CHUNK` this bounds the memory one download can hold
+const CHANNEL_CHUNKS: usize = 4;
+/// Entries `dir_stats` counts before leaving a directory to be counted
+/// when it's archived
+pub const MAX_COUNTED_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+        }
+    }
+
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+        }
+    }
+}
+
+/// Totals of a shared directory, as it would be archived
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirStats {
+    /// Files and subdirectories
+    pub entries: i64,
+    /// Sum of the file sizes
+    pub total_bytes: i64,
+}
+
+impl DirStats {
+    fn add(&mut self, entry: &DirEntry) {
+        self.entries += 1;
+        self.total_bytes += i64::try_from(entry.size).unwrap_or(i64::MAX);
+    }
+}
+
+/// One file or subdirectory below a shared directory
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// Path below the shared directory, `/` separated
+    pub name: String,
+    /// Where to read it from, the link target for followed symlinks
+    pub source: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub mtime: Option<i64>,
+}
+
+/// Walks `root` (canonical) in name order without loading the tree.
+///
+/// Entries the path policy denies are skipped, denied directories with
+/// everything below them. Symlinks are only followed if they resolve to a
+/// regular file inside `root`; links to directories are skipped too, their
+/// content is either archived under its real path already or outside.
+pub fn walk<'a>(root: &'a Path, policy: &'a PathPolicy) -> impl Iterator<Item = DirEntry> + 'a {
+    walk_below(root, root, usize::MAX, policy)
+}
+
+/// `walk` limited to `start` (a directory below `root`) and `max_depth`
+/// levels under it. Names stay relative to `root`.
+fn walk_below<'a>(
+    root: &'a Path,
+    start: &Path,
+    max_depth: usize,
+    policy: &'a PathPolicy,
+) -> impl Iterator<Item = DirEntry> + 'a {
+    let mut it = WalkDir::new(start)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter();
+
+    std::iter::from_fn(move || loop {
+        let entry = match it.next()? {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("skipping unreadable entry: {e}");
+                continue;
+            }
+        };
+        match dir_entry(root, entry.path(), entry.file_type(), policy) {
+            Some(entry) => return Some(entry),
+            None if entry.file_type().is_dir() => it.skip_current_dir(),
+            None => {}
+        }
+    })
+}
+
+/// Applies the rules of `walk` to one path below `root`, `None` if it
+/// would be skipped
+fn dir_entry(
+    root: &Path,
+    path: &Path,
+    file_type: std::fs::FileType,
+    policy: &PathPolicy,
+) -> Option<DirEntry> {
+    let source = if file_type.is_symlink() {
+        match std::fs::canonicalize(path) {
+            Ok(target) if target.starts_with(root) && target.is_file() => target,
+            _ => {
+                log::debug!("not following symlink {}", path.display());
+                return None;
+            }
+        }
+    } else if file_type.is_dir() || file_type.is_file() {
+        path.to_path_buf()
+    } else {
+        return None;
+    };
+
+    if !policy.allows(&source) {
+        return None;
+    }
+
+    let metadata = match std::fs::metadata(&source) {
+        Ok(m) => m,
+        Err(e) => {
+            log::warn!("skipping {}: {e}", path.display());
+            return None;
+        }
+    };
+    let name = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some(DirEntry {
+        name,
+        source,
+        is_dir: metadata.is_dir(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        mtime: crate::db::unix_mtime(&metadata),
+    })
+}
+
+/// Finds the entry `walk` would list as `rel` (`/` separated, empty for
+/// `root` itself). `None` for anything `walk` wouldn't reach, which
+/// covers `.` / `..` / empty components, symlinks anywhere but at the
+/// end, links leaving `root`, denied and missing paths.
+#[must_use]
+pub fn resolve(root: &Path, rel: &str, policy: &PathPolicy) -> Option<DirEntry> {
+    let rel = rel.trim_start_matches('/');
+    let mut path = root.to_path_buf();
+    let mut entry = dir_entry(
+        root,
+        root,
+        std::fs::metadata(root).ok()?.file_type(),
+        policy,
+    )?;
+
+    if rel.is_empty() {
+        return entry.is_dir.then_some(entry);
+    }
+    for part in rel.split('/') {
+        // Only directories the walk descends into can be passed through
+        if !entry.is_dir || entry.source != path || matches!(part, "" | "." | "..") {
+            return None;
+        }
+        path.push(part);
+        let file_type = std::fs::symlink_metadata(&path).ok()?.file_type();
+        entry = dir_entry(root, &path, file_type, policy)?;
+    }
+    Some(entry)
+}
+
+/// Direct children of the directory `rel` below `root`, in name order.
+/// `None` if `resolve` doesn't find a directory there.
+#[must_use]
+pub fn list_dir(root: &Path, rel: &str, policy: &PathPolicy) -> Option<Vec<DirEntry>> {
+    let dir = resolve(root, rel, policy).filter(|d| d.is_dir)?;
+    Some(walk_below(root, &dir.source, 1, policy).collect())
+}
+
+/// Counts what `write_archive` would put in the archive, giving up with
+/// `None` past `MAX_COUNTED_ENTRIES` so registering a huge tree doesn't
+/// walk all of it. Archiving a directory counts it in full anyway.
+#[must_use]
+pub fn dir_stats(root: &Path, policy: &PathPolicy) -> Option<DirStats> {
+    let mut stats = DirStats::default();
+    for (n, entry) in walk(root, policy).enumerate() {
+        if n >= MAX_COUNTED_ENTRIES {
+            return None;
+        }
+        stats.add(&entry);
+    }
+    Some(stats)
+}
+
+/// Reads exactly `size` bytes: a file that grew is cut, one that shrank
+/// padded with zeros, so headers written up front stay true
+fn exact(file: File, size: u64) -> impl Read {
+    file.take(size).chain(io::repeat(0)).take(size)
+}
+
+/// Writes `root` as an archive whose entries sit under the directory's
+/// own name. File data is streamed through and the zip central directory
+/// spooled to a temporary file, memory use doesn't grow with the tree.
+///
+/// # Errors
+///
+/// Will error if writing to `out` fails
+pub fn write_archive<W: Write>(
+    format: ArchiveFormat,
+    root: &Path,
+    policy: &PathPolicy,
+    out: W,
+) -> io::Result<DirStats> {
+    let prefix = root
+        .file_name()
+        .map_or_else(|| "archive".into(), |n| n.to_string_lossy().into_owned());
+    write_entries(format, &prefix, walk(root, policy), out)
+}
+
+/// Writes `entries` as an archive, each under `prefix/`
+///
+/// # Errors
+///
+/// Will error if writing to `out` fails
+pub fn write_entries<W: Write>(
+    format: ArchiveFormat,
+    prefix: &str,
+    entries: impl IntoIterator<Item = DirEntry>,
+    out: W,
+) -> io::Result<DirStats> {
+    let mut stats = DirStats::default();
+
+    match format {
+        ArchiveFormat::Tar => {
+            let mut tar = tar::Builder::new(out);
+            for entry in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_mtime(entry.mtime.and_then(|m| u64::try_from(m).ok()).unwrap_or(0));
+                if entry.is_dir {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    header.set_size(0);
+                    tar.append_data(
+                        &mut header,
+                        format!("{prefix}/{}/", entry.name),
+                        io::empty(),
+                    )?;
+                } else {
+                    let Some(file) = open_entry(&entry) else {
+                        continue;
+                    };
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(0o644);
+                    header.set_size(entry.size);
+                    let name = format!("{prefix}/{}", entry.name);
+                    tar.append_data(&mut header, name, exact(file, entry.size))?;
+                }
+                stats.add(&entry);
+            }
+            tar.into_inner()?;
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = ZipStream::new(out)?;
+            for entry in entries {
+                if entry.is_dir {
+                    zip.add_dir(&format!("{prefix}/{}/", entry.name), entry.mtime)?;
+                } else {
+                    let Some(file) = open_entry(&entry) else {
+                        continue;
+                    };
+                    let name = format!("{prefix}/{}", entry.name);
+                    zip.add_file(&name, entry.mtime, entry.size, exact(file, entry.size))?;
+                }
+                stats.add(&entry);
+            }
+            zip.finish()?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Files can vanish between the walk and reading them, those are left out
+fn open_entry(entry: &DirEntry) -> Option<File> {
+    File::open(&entry.source)
+        .inspect_err(|e| log::warn!("skipping {}: {e}", entry.source.display()))
+        .ok()
+}
+
+/// What `stream_archive` packs
+#[derive(Debug, Clone)]
+pub enum ArchiveSource {
+    /// A shared directory, walked while archiving
+    Dir { root: PathBuf, policy: PathPolicy },
+    /// A fixed list of files, stored under `name/`
+    Files { name: String, files: Vec<DirEntry> },
+}
+
+impl ArchiveSource {
+    fn write(self, format: ArchiveFormat, out: impl Write) -> io::Result<DirStats> {
+        match self {
+            Self::Dir { root, policy } => write_archive(format, &root, &policy, out),
+            Self::Files { name, files } => write_entries(format, &name, files, out),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Dir { root, .. } => root.display().to_string(),
+            Self::Files { name, .. } => name.clone(),
+        }
+    }
+}
+
+/// Generates the archive on its own thread and hands it over in chunks.
+/// At most `CHANNEL_CHUNKS` chunks are buffered, a slow client slows the
+/// archiver down instead of piling up memory. `on_done` gets the totals
+/// once the whole archive was sent.
+///
+/// # Errors
+///
+/// Will error if the thread can't be started
+pub fn stream_archive(
+    format: ArchiveFormat,
+    source: ArchiveSource,
+    on_done: impl FnOnce(DirStats) + Send + 'static,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>>> {
+    let (tx, rx) = mpsc::channel(CHANNEL_CHUNKS);
+    let what = source.describe();
+
+    std::thread::Builder::new()
+        .name("archive".into())
+        .spawn(move || {
+            let mut out = ChannelWriter {
+                tx: tx.clone(),
+                buf: Vec::with_capacity(CHUNK),
+            };
+            let written = source
+                .write(format, &mut out)
+                .and_then(|stats| out.flush().map(|()| stats));
+            match written {
+                Ok(stats) => on_done(stats),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    log::info!("archive of {what} aborted by client");
+                }
+                Err(e) => {
+                    log::warn!("archiving {what} failed: {e}");
+                    // Fails the response so the client doesn't take a cut archive as complete
+                    let _ = tx.blocking_send(Err(e));
+                }
+            }
+        })?;
+
+    Ok(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    }))
+}
+
+/// `Write` end of the channel `stream_archive` reads from
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK));
+        self.tx
+            .blocking_send(Ok(Bytes::from(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(CHUNK - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == CHUNK {
+            self.send()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+// ——— Zip (store mode) ———
+//
+// Written front to back without seeking: sizes and CRC follow each file
+// in a data descriptor, ZIP64 fields are added only where a value needs it.
+
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+/// Made by unix, spec version 4.5
+const VERSION_MADE_BY: u16 = (3 << 8) | 45;
+/// Data descriptor follows the data, names are UTF-8
+const FLAG_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    time: u16,
+    date: u16,
+    is_dir: bool,
+}
+
+struct ZipStream<W: Write> {
+    out: W,
+    written: u64,
+    /// Central directory records, spooled to disk until `finish` appends
+    /// them so memory doesn't grow with the number of entries
+    central: io::BufWriter<File>,
+    entries: u64,
+}
+
+impl<W: Write> ZipStream<W> {
+    fn new(out: W) -> io::Result<Self> {
+        Ok(Self {
+            out,
+            written: 0,
+            central: io::BufWriter::new(tempfile::tempfile()?),
+            entries: 0,
+        })
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn add_dir(&mut self, name: &str, mtime: Option<i64>) -> io::Result<()> {
+        let (time, date) = dos_datetime(mtime);
+        let offset = self.written;
+
+        let mut h = Vec::with_capacity(30 + name.len());
+        h.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        h.extend_from_slice(&20u16.to_le_bytes());
+        h.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        h.extend_from_slice(&0u16.to_le_bytes()); // stored
+        h.extend_from_slice(&time.to_le_bytes());
+        h.extend_from_slice(&date.to_le_bytes());
+        h.extend_from_slice(&[0; 12]); // crc, sizes
+        h.extend_from_slice(&name_len(name)?.to_le_bytes());
+        h.extend_from_slice(&0u16.to_le_bytes());
+        h.extend_from_slice(name.as_bytes());
+        self.put(&h)?;
+
+        self.record(&CentralEntry {
+            name: name.to_string(),
+            crc: 0,
+            size: 0,
+            offset,
+            time,
+            date,
+            is_dir: true,
+        })
+    }
+
+    fn add_file(
+        &mut self,
+        name: &str,
+        mtime: Option<i64>,
+        size: u64,
+        mut data: impl Read,
+    ) -> io::Result<()> {
+        let (time, date) = dos_datetime(mtime);
+        let offset = self.written;
+        let zip64 = size >= ZIP64_LIMIT;
+
+        let mut h = Vec::with_capacity(50 + name.len());
+        h.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        h.extend_from_slice(&(if zip64 { 45u16 } else { 20 }).to_le_bytes());
+        h.extend_from_slice(&(FLAG_DESCRIPTOR | FLAG_UTF8).to_le_bytes());
+        h.extend_from_slice(&0u16.to_le_bytes()); // stored
+        h.extend_from_slice(&time.to_le_bytes());
+        h.extend_from_slice(&date.to_le_bytes());
+        h.extend_from_slice(&0u32.to_le_bytes()); // crc, in the descriptor
+        let placeholder: u32 = if zip64 { 0xFFFF_FFFF } else { 0 };
+        h.extend_from_slice(&placeholder.to_le_bytes());
+        h.extend_from_slice(&placeholder.to_le_bytes());
+        h.extend_from_slice(&name_len(name)?.to_le_bytes());
+        h.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        h.extend_from_slice(name.as_bytes());
+        if zip64 {
+            // Sizes come in the descriptor, this only announces 8 byte ones
+            h.extend_from_slice(&1u16.to_le_bytes());
+            h.extend_from_slice(&16u16.to_le_bytes());
+            h.extend_from_slice(&[0; 16]);
+        }
+        self.put(&h)?;
+
+        let mut crc = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; CHUNK];
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            self.put(&buf[..n])?;
+        }
+        let crc = crc.finalize();
+
+        let mut d = Vec::with_capacity(24);
+        d.extend_from_slice(&0x0807_4b50u32.to_le_bytes());
+        d.extend_from_slice(&crc.to_le_bytes());
+        if zip64 {
+            d.extend_from_slice(&size.to_le_bytes());
+            d.extend_from_slice(&size.to_le_bytes());
+        } else {
+            d.extend_from_slice(&(size as u32).to_le_bytes());
+            d.extend_from_slice(&(size as u32).to_le_bytes());
+        }
+        self.put(&d)?;
+
+        self.record(&CentralEntry {
+            name: name.to_string(),
+            crc,
+            size,
+            offset,
+            time,
+            date,
+            is_dir: false,
+        })
+    }
+
+    /// Spools the central directory record of an entry written just now
+    fn record(&mut self, entry: &CentralEntry) -> io::Result<()> {
+        let big_size = entry.size >= ZIP64_LIMIT;
+        let big_offset = entry.offset >= ZIP64_LIMIT;
+        let mut extra = Vec::new();
+        if big_size {
+            extra.extend_from_slice(&entry.size.to_le_bytes());
+            extra.extend_from_slice(&entry.size.to_le_bytes());
+        }
+        if big_offset {
+            extra.extend_from_slice(&entry.offset.to_le_bytes());
+        }
+        if !extra.is_empty() {
+            let len = extra.len() as u16;
+            extra.splice(0..0, [1u16.to_le_bytes(), len.to_le_bytes()].concat());
+        }
+
+        let flags = if entry.is_dir {
+            FLAG_UTF8
+        } else {
+            FLAG_DESCRIPTOR | FLAG_UTF8
+        };
+        let attrs: u32 = if entry.is_dir {
+            (0o040_755 << 16) | 0x10
+        } else {
+            0o100_644 << 16
+        };
+        let size32 = if big_size {
+            0xFFFF_FFFF
+        } else {
+            entry.size as u32
+        };
+        let offset32 = if big_offset {
+            0xFFFF_FFFF
+        } else {
+            entry.offset as u32
+        };
+
+        let mut h = Vec::with_capacity(46 + entry.name.len() + extra.len());
+        h.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        h.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        h.extend_from_slice(&(if extra.is_empty() { 20u16 } else { 45 }).to_le_bytes());
+        h.extend_from_slice(&flags.to_le_bytes());
+        h.extend_from_slice(&0u16.to_le_bytes()); // stored
+        h.extend_from_slice(&entry.time.to_le_bytes());
+        h.extend_from_slice(&entry.date.to_le_bytes());
+        h.extend_from_slice(&entry.crc.to_le_bytes());
+        h.extend_from_slice(&size32.to_le_bytes());
+        h.extend_from_slice(&size32.to_le_bytes());
+        h.extend_from_slice(&name_len(&entry.name)?.to_le_bytes());
+        h.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        h.extend_from_slice(&[0; 6]); // comment len, disk, internal attrs
+        h.extend_from_slice(&attrs.to_le_bytes());
+        h.extend_from_slice(&offset32.to_le_bytes());
+        h.extend_from_slice(entry.name.as_bytes());
+        h.extend_from_slice(&extra);
+        self.central.write_all(&h)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let cd_offset = self.written;
+        let count = self.entries;
+        self.central.flush()?;
+        let mut central = self.central.get_ref().try_clone()?;
+        central.seek(SeekFrom::Start(0))?;
+        let mut buf = vec![0u8; CHUNK];
+        loop {
+            let n = central.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.put(&buf[..n])?;
+        }
+        let cd_size = self.written - cd_offset;
+
+        let mut e = Vec::with_capacity(98);
+        if count >= 0xFFFF || cd_offset >= ZIP64_LIMIT || cd_size >= ZIP64_LIMIT {
+            let eocd64_offset = self.written;
+            e.extend_from_slice(&0x0606_4b50u32.to_le_bytes());
+            e.extend_from_slice(&44u64.to_le_bytes());
+            e.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+            e.extend_from_slice(&45u16.to_le_bytes());
+            e.extend_from_slice(&[0; 8]); // disk numbers
+            e.extend_from_slice(&count.to_le_bytes());
+            e.extend_from_slice(&count.to_le_bytes());
+            e.extend_from_slice(&cd_size.to_le_bytes());
+            e.extend_from_slice(&cd_offset.to_le_bytes());
+
+            e.extend_from_slice(&0x0706_4b50u32.to_le_bytes());
+            e.extend_from_slice(&0u32.to_le_bytes());
+            e.extend_from_slice(&eocd64_offset.to_le_bytes());
+            e.extend_from_slice(&1u32.to_le_bytes());
+        }
+        e.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        e.extend_from_slice(&[0; 4]); // disk numbers
+        let count16 = count.min(0xFFFF) as u16;
+        e.extend_from_slice(&count16.to_le_bytes());
+        e.extend_from_slice(&count16.to_le_bytes());
+        e.extend_from_slice(&(cd_size.min(ZIP64_LIMIT) as u32).to_le_bytes());
+        e.extend_from_slice(&(cd_offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+        e.extend_from_slice(&0u16.to_le_bytes());
+        self.put(&e)?;
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn name_len(name: &str) -> io::Result<u16> {
+    u16::try_from(name.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry name too long for zip"))
+}
+
+/// MS-DOS time and date, clamped to the 1980..=2107 range it can express
+fn dos_datetime(mtime: Option<i64>) -> (u16, u16) {
+    const DOS_EPOCH: i64 = 315_532_800; // 1980-01-01
+    let secs = mtime.unwrap_or(DOS_EPOCH).max(DOS_EPOCH);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let rem = secs.rem_euclid(86_400);
+    if year > 2107 {
+        return (0xBF7D, 0xFF9F); // 2107-12-31 23:59:58
+    }
+
+    let time = ((rem / 3600) << 11) | (((rem % 3600) / 60) << 5) | ((rem % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+/// Days since 1970-01-01 to (year, month, day), proleptic Gregorian
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,173 @@
+// src/auth.rs
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header::AUTHORIZATION, Method},
+    middleware::Next,
+    web, HttpMessage, HttpRequest,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::db::{Admin, Db, NewAdminAudit};
+use crate::download::client_ip;
+use crate::error::ServiceError;
+
+/// Cookie set on login, scoped to `/admin`
+pub const SESSION_COOKIE: &str = "fs_session";
+
+/// Pulls the session token from `Authorization: Bearer …` or the session cookie
+#[must_use]
+pub fn session_token(req: &actix_web::HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+
+    bearer.or_else(|| req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()))
+}
+
+/// Middleware guarding the `/admin` scope.
+/// On success the logged in `Admin` is put in the request extensions.
+///
+/// # Errors
+///
+/// `Unauthorized` if there is no token or it's unknown / expired
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = session_token(req.request()).ok_or(ServiceError::Unauthorized)?;
+
+    // Fails closed if the app wasn't given a Db
+    let db = req
+        .app_data::<web::Data<Db>>()
+        .ok_or(ServiceError::Unauthorized)?
+        .clone();
+    let admin = db
+        .blocking(move |db| db.get_session_admin(&token))
+        .await?
+        .ok_or(ServiceError::Unauthorized)?;
+
+    req.extensions_mut().insert(admin);
+    next.call(req).await
+}
+
+/// Keys whose values never reach the audit trail
+const SECRET_KEYS: &[&str] = &["password", "token", "secret"];
+
+/// Replaces the values of secret looking keys, at any depth
+#[must_use]
+pub fn redact(mut value: Value) -> Value {
+    match &mut value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if SECRET_KEYS.iter().any(|s| key.contains(s)) {
+                    if !v.is_null() {
+                        *v = Value::String("[redacted]".into());
+                    }
+                } else {
+                    *v = redact(v.take());
+                }
+            }
+        }
+        Value::Array(items) => {
+            for v in items.iter_mut() {
+                *v = redact(v.take());
+            }
+        }
+        _ => {}
+    }
+    value
+}
+
+/// Request extension with what `audit_admin` can't see by itself: the
+/// target a call created and its parameters
+#[derive(Debug, Clone, Default)]
+pub struct AuditNote {
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    pub params: Option<Value>,
+}
+
+/// Attaches the parameters of an admin call to its audit entry, redacted
+pub fn audit_params(req: &HttpRequest, params: &impl Serialize) {
+    let params = serde_json::to_value(params).ok().map(redact);
+    req.extensions_mut()
+        .get_or_insert_with(AuditNote::default)
+        .params = params;
+}
+
+/// Names what an admin call acted on, when the route doesn't, e.g. the
+/// slug of a share it created
+pub fn audit_target(req: &HttpRequest, target: impl ToString) {
+    req.extensions_mut()
+        .get_or_insert_with(AuditNote::default)
+        .target = Some(target.to_string());
+}
+
+/// Middleware around the whole `/admin` scope: records every call that
+/// isn't a GET in the admin audit trail, failed ones included. The action
+/// is the method and route, the target what the route names unless the
+/// handler set an `AuditNote`.
+///
+/// # Errors
+///
+/// Only passes on errors of the wrapped handler, a failed audit write is
+/// logged
+pub async fn audit_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.call(req).await;
+    }
+    let Some(db) = req.app_data::<web::Data<Db>>().cloned() else {
+        return next.call(req).await;
+    };
+    let ip = client_ip(req.request());
+    // Until routed the pattern is only the scope's
+    let fallback_action = format!("{} {}", req.method(), req.path());
+
+    let res = next.call(req).await;
+    let entry = match &res {
+        Ok(res) => {
+            let req = res.request();
+            let action = match req.match_pattern() {
+                Some(pattern) => format!("{} {pattern}", req.method()),
+                None => fallback_action,
+            };
+            let parts: Vec<&str> = req.match_info().iter().map(|(_, v)| v).collect();
+            let note = req
+                .extensions_mut()
+                .remove::<AuditNote>()
+                .unwrap_or_default();
+            let actor = req
+                .extensions()
+                .get::<Admin>()
+                .map(|a| a.username.clone())
+                .or(note.actor);
+            NewAdminAudit {
+                actor,
+                action,
+                target: note.target.or((!parts.is_empty()).then(|| parts.join("/"))),
+                params: note.params,
+                ip,
+                status: res.status().as_u16(),
+            }
+        }
+        // Refused before reaching a handler, e.g. not logged in
+        Err(e) => NewAdminAudit {
+            action: fallback_action,
+            ip,
+            status: e.as_response_error().status_code().as_u16(),
+            ..NewAdminAudit::default()
+        },
+    };
+    if let Err(e) = db.blocking(move |db| db.record_admin_audit(&entry)).await {
+        log::error!("could not record admin audit entry: {e}");
+    }
+    res
+}
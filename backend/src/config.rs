@@ -0,0 +1,462 @@
+// src/config.rs
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::download::TrustedProxies;
+use crate::janitor::JanitorSettings;
+use crate::policy::{ExpiredSharePolicy, FileChangePolicy, LockoutPolicy, PathPolicy};
+use crate::slug::SlugAlphabet;
+use crate::throttle::ThrottleLimits;
+
+/// Used when `--config` isn't given, only if it exists
+const DEFAULT_CONFIG_FILE: &str = "file-serve.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Clean up the database and upload dir once, vacuum included, and exit
+    Janitor,
+}
+
+/// Command line flags. Every flag can also come from its `FILE_SERVE_*`
+/// env var; both win over the config file.
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Share files from this host through short links")]
+pub struct Cli {
+    /// Runs the server if not given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML config file
+    #[arg(short, long, env = "FILE_SERVE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind to
+    #[arg(long, env = "FILE_SERVE_BIND")]
+    pub bind: Option<String>,
+
+    #[arg(short, long, env = "FILE_SERVE_PORT")]
+    pub port: Option<u16>,
+
+    /// Worker threads, defaults to one per core
+    #[arg(long, env = "FILE_SERVE_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// SQLite database file
+    #[arg(long, env = "FILE_SERVE_DB")]
+    pub db_path: Option<PathBuf>,
+
+    /// Directory files may be shared from, repeatable (`:` separated in env)
+    #[arg(long = "share-root", env = "FILE_SERVE_ROOTS", value_delimiter = ':')]
+    pub share_roots: Vec<PathBuf>,
+
+    /// Glob of paths that are never shareable, repeatable (`,` separated in env)
+    #[arg(long = "deny", env = "FILE_SERVE_DENY", value_delimiter = ',')]
+    pub deny_globs: Vec<String>,
+
+    /// What to do when a shared file changed on disk
+    #[arg(long, env = "FILE_SERVE_ON_FILE_CHANGE")]
+    pub on_file_change: Option<FileChangePolicy>,
+
+    /// Length of generated share slugs, the `words` alphabet ignores it
+    #[arg(long, env = "FILE_SERVE_SLUG_LENGTH")]
+    pub slug_length: Option<usize>,
+
+    /// What generated share slugs are made of
+    #[arg(long, env = "FILE_SERVE_SLUG_ALPHABET")]
+    pub slug_alphabet: Option<SlugAlphabet>,
+
+    /// Largest accepted request body, in bytes
+    #[arg(long, env = "FILE_SERVE_MAX_BODY")]
+    pub max_body_bytes: Option<usize>,
+
+    #[arg(long, env = "FILE_SERVE_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Public URL of the frontend, used to build share links
+    #[arg(long, env = "FILE_SERVE_PUBLIC_URL")]
+    pub public_base_url: Option<String>,
+
+    /// Directory uploaded files are stored in, always shareable
+    #[arg(long, env = "FILE_SERVE_UPLOAD_DIR")]
+    pub upload_dir: Option<PathBuf>,
+
+    /// Largest accepted upload, in bytes
+    #[arg(long, env = "FILE_SERVE_MAX_UPLOAD")]
+    pub max_upload_bytes: Option<u64>,
+
+    /// Wrong share passwords allowed per slug / IP before locking out
+    #[arg(long, env = "FILE_SERVE_PASSWORD_ATTEMPTS")]
+    pub password_attempts: Option<u32>,
+
+    /// First lockout in seconds, doubled with every further failure
+    #[arg(long, env = "FILE_SERVE_LOCKOUT_SECS")]
+    pub lockout_secs: Option<u64>,
+
+    /// Longest lockout in seconds
+    #[arg(long, env = "FILE_SERVE_MAX_LOCKOUT_SECS")]
+    pub max_lockout_secs: Option<u64>,
+
+    /// Download requests per minute allowed from one IP
+    #[arg(long, env = "FILE_SERVE_DOWNLOAD_RATE")]
+    pub download_rate_per_minute: Option<u32>,
+
+    /// Downloads running at once from one IP
+    #[arg(long, env = "FILE_SERVE_MAX_DOWNLOADS_PER_IP")]
+    pub max_downloads_per_ip: Option<u32>,
+
+    /// Downloads of one share running at once, shares can override it
+    #[arg(long, env = "FILE_SERVE_MAX_DOWNLOADS_PER_SHARE")]
+    pub max_downloads_per_share: Option<u32>,
+
+    /// Bandwidth of all downloads together, in bytes per second
+    #[arg(long, env = "FILE_SERVE_BANDWIDTH")]
+    pub bandwidth_bytes_per_sec: Option<u64>,
+
+    /// Bandwidth of one share's downloads, in bytes per second, shares can
+    /// override it
+    #[arg(long, env = "FILE_SERVE_SHARE_BANDWIDTH")]
+    pub share_bandwidth_bytes_per_sec: Option<u64>,
+
+    /// Seconds between janitor runs, 0 turns the janitor off
+    #[arg(long, env = "FILE_SERVE_JANITOR_INTERVAL")]
+    pub janitor_interval_secs: Option<u64>,
+
+    /// Seconds between VACUUMs by the janitor, 0 for never
+    #[arg(long, env = "FILE_SERVE_VACUUM_INTERVAL")]
+    pub vacuum_interval_secs: Option<u64>,
+
+    /// What the janitor does with expired and used up shares
+    #[arg(long, env = "FILE_SERVE_ON_SHARE_EXPIRED")]
+    pub on_share_expired: Option<ExpiredSharePolicy>,
+
+    /// Seconds a registered file is kept without a share
+    #[arg(long, env = "FILE_SERVE_ORPHAN_GRACE")]
+    pub orphan_grace_secs: Option<u64>,
+
+    /// Also delete uploads from disk once no share refers to them
+    #[arg(long, env = "FILE_SERVE_PURGE_ORPHAN_UPLOADS")]
+    pub purge_orphan_uploads: Option<bool>,
+
+    /// Reverse proxy address or CIDR range whose X-Forwarded-For is
+    /// believed, repeatable (`,` separated in env)
+    #[arg(
+        long = "trusted-proxy",
+        env = "FILE_SERVE_TRUSTED_PROXIES",
+        value_delimiter = ','
+    )]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Server settings, resolved as defaults < config file < env < CLI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub workers: Option<usize>,
+    pub db_path: PathBuf,
+    pub share_roots: Vec<PathBuf>,
+    pub deny_globs: Vec<String>,
+    pub on_file_change: FileChangePolicy,
+    pub slug_length: usize,
+    pub slug_alphabet: SlugAlphabet,
+    pub max_body_bytes: usize,
+    pub log_format: LogFormat,
+    pub public_base_url: Option<String>,
+    pub upload_dir: PathBuf,
+    pub max_upload_bytes: u64,
+    pub password_attempts: u32,
+    pub lockout_secs: u64,
+    pub max_lockout_secs: u64,
+    pub download_rate_per_minute: Option<u32>,
+    pub max_downloads_per_ip: Option<u32>,
+    pub max_downloads_per_share: Option<u32>,
+    pub bandwidth_bytes_per_sec: Option<u64>,
+    pub share_bandwidth_bytes_per_sec: Option<u64>,
+    pub janitor_interval_secs: u64,
+    pub vacuum_interval_secs: u64,
+    pub on_share_expired: ExpiredSharePolicy,
+    pub orphan_grace_secs: u64,
+    pub purge_orphan_uploads: bool,
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".into(),
+            port: 8080,
+            workers: None,
+            db_path: "data.db".into(),
+            share_roots: Vec::new(),
+            deny_globs: Vec::new(),
+            on_file_change: FileChangePolicy::default(),
+            slug_length: 8,
+            slug_alphabet: SlugAlphabet::default(),
+            max_body_bytes: 256 * 1024,
+            log_format: LogFormat::Text,
+            public_base_url: None,
+            upload_dir: "uploads".into(),
+            max_upload_bytes: 1024 * 1024 * 1024,
+            password_attempts: LockoutPolicy::default().free_attempts,
+            lockout_secs: LockoutPolicy::default().base_secs,
+            max_lockout_secs: LockoutPolicy::default().max_secs,
+            download_rate_per_minute: None,
+            max_downloads_per_ip: None,
+            max_downloads_per_share: None,
+            bandwidth_bytes_per_sec: None,
+            share_bandwidth_bytes_per_sec: None,
+            janitor_interval_secs: 60 * 60,
+            vacuum_interval_secs: 7 * 24 * 60 * 60,
+            on_share_expired: ExpiredSharePolicy::default(),
+            orphan_grace_secs: 24 * 60 * 60,
+            purge_orphan_uploads: false,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Config {
+    /// # Errors
+    ///
+    /// Will error if the file can't be read or isn't valid TOML for `Config`
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        toml::from_str(&raw).map_err(|e| invalid(format!("{}: {e}", path.display())))
+    }
+
+    /// Reads the config file (if any) and layers the CLI / env values on top
+    ///
+    /// # Errors
+    ///
+    /// Will error if an explicitly given config file is missing or invalid,
+    /// or the result fails `validate`
+    pub fn load(cli: Cli) -> io::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(v) = cli.bind {
+            self.bind = v;
+        }
+        if let Some(v) = cli.port {
+            self.port = v;
+        }
+        if cli.workers.is_some() {
+            self.workers = cli.workers;
+        }
+        if let Some(v) = cli.db_path {
+            self.db_path = v;
+        }
+        if !cli.share_roots.is_empty() {
+            self.share_roots = cli.share_roots;
+        }
+        if !cli.deny_globs.is_empty() {
+            self.deny_globs = cli.deny_globs;
+        }
+        if let Some(v) = cli.on_file_change {
+            self.on_file_change = v;
+        }
+        if let Some(v) = cli.slug_length {
+            self.slug_length = v;
+        }
+        if let Some(v) = cli.slug_alphabet {
+            self.slug_alphabet = v;
+        }
+        if let Some(v) = cli.max_body_bytes {
+            self.max_body_bytes = v;
+        }
+        if let Some(v) = cli.log_format {
+            self.log_format = v;
+        }
+        if cli.public_base_url.is_some() {
+            self.public_base_url = cli.public_base_url;
+        }
+        if let Some(v) = cli.upload_dir {
+            self.upload_dir = v;
+        }
+        if let Some(v) = cli.max_upload_bytes {
+            self.max_upload_bytes = v;
+        }
+        if let Some(v) = cli.password_attempts {
+            self.password_attempts = v;
+        }
+        if let Some(v) = cli.lockout_secs {
+            self.lockout_secs = v;
+        }
+        if let Some(v) = cli.max_lockout_secs {
+            self.max_lockout_secs = v;
+        }
+        if cli.download_rate_per_minute.is_some() {
+            self.download_rate_per_minute = cli.download_rate_per_minute;
+        }
+        if cli.max_downloads_per_ip.is_some() {
+            self.max_downloads_per_ip = cli.max_downloads_per_ip;
+        }
+        if cli.max_downloads_per_share.is_some() {
+            self.max_downloads_per_share = cli.max_downloads_per_share;
+        }
+        if cli.bandwidth_bytes_per_sec.is_some() {
+            self.bandwidth_bytes_per_sec = cli.bandwidth_bytes_per_sec;
+        }
+        if cli.share_bandwidth_bytes_per_sec.is_some() {
+            self.share_bandwidth_bytes_per_sec = cli.share_bandwidth_bytes_per_sec;
+        }
+        if let Some(v) = cli.janitor_interval_secs {
+            self.janitor_interval_secs = v;
+        }
+        if let Some(v) = cli.vacuum_interval_secs {
+            self.vacuum_interval_secs = v;
+        }
+        if let Some(v) = cli.on_share_expired {
+            self.on_share_expired = v;
+        }
+        if let Some(v) = cli.orphan_grace_secs {
+            self.orphan_grace_secs = v;
+        }
+        if let Some(v) = cli.purge_orphan_uploads {
+            self.purge_orphan_uploads = v;
+        }
+        if !cli.trusted_proxies.is_empty() {
+            self.trusted_proxies = cli.trusted_proxies;
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Describes the first out of range setting
+    pub fn validate(&self) -> io::Result<()> {
+        if !(4..=64).contains(&self.slug_length) {
+            return Err(invalid(format!(
+                "slug_length must be between 4 and 64, got {}",
+                self.slug_length
+            )));
+        }
+        if self.workers == Some(0) {
+            return Err(invalid("workers must be at least 1".into()));
+        }
+        if self.max_body_bytes == 0 {
+            return Err(invalid("max_body_bytes must be at least 1".into()));
+        }
+        if self.max_upload_bytes == 0 {
+            return Err(invalid("max_upload_bytes must be at least 1".into()));
+        }
+        if self.lockout_secs == 0 || self.max_lockout_secs < self.lockout_secs {
+            return Err(invalid(format!(
+                "lockout_secs must be at least 1 and at most max_lockout_secs ({}), got {}",
+                self.max_lockout_secs, self.lockout_secs
+            )));
+        }
+        let limits = [
+            (
+                "download_rate_per_minute",
+                self.download_rate_per_minute.map(u64::from),
+            ),
+            (
+                "max_downloads_per_ip",
+                self.max_downloads_per_ip.map(u64::from),
+            ),
+            (
+                "max_downloads_per_share",
+                self.max_downloads_per_share.map(u64::from),
+            ),
+            ("bandwidth_bytes_per_sec", self.bandwidth_bytes_per_sec),
+            (
+                "share_bandwidth_bytes_per_sec",
+                self.share_bandwidth_bytes_per_sec,
+            ),
+        ];
+        if let Some((name, _)) = limits.iter().find(|(_, v)| *v == Some(0)) {
+            return Err(invalid(format!(
+                "{name} must be at least 1, leave it out for no limit"
+            )));
+        }
+        self.trusted_proxies()?;
+        Ok(())
+    }
+
+    /// Builds the share path policy. With no roots configured only the
+    /// working directory is shareable. The upload dir is added as a root
+    /// once it exists, so create the `Storage` first.
+    ///
+    /// # Errors
+    ///
+    /// Will error if a root doesn't exist or a deny glob is invalid
+    pub fn path_policy(&self) -> io::Result<PathPolicy> {
+        let mut roots = self.share_roots.clone();
+        if roots.is_empty() {
+            log::warn!("no share_roots configured, only the working directory is shareable");
+            roots.push(std::env::current_dir()?);
+        }
+        if self.upload_dir.is_dir() {
+            roots.push(self.upload_dir.clone());
+        }
+        PathPolicy::new(&roots, &self.deny_globs)
+    }
+
+    #[must_use]
+    pub fn lockout_policy(&self) -> LockoutPolicy {
+        LockoutPolicy {
+            free_attempts: self.password_attempts,
+            base_secs: self.lockout_secs,
+            max_secs: self.max_lockout_secs,
+        }
+    }
+
+    #[must_use]
+    pub fn throttle_limits(&self) -> ThrottleLimits {
+        ThrottleLimits {
+            requests_per_minute: self.download_rate_per_minute,
+            per_ip: self.max_downloads_per_ip,
+            per_share: self.max_downloads_per_share,
+            bytes_per_sec: self.bandwidth_bytes_per_sec,
+            share_bytes_per_sec: self.share_bandwidth_bytes_per_sec,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will error if an entry isn't an IP address or CIDR range
+    pub fn trusted_proxies(&self) -> io::Result<TrustedProxies> {
+        TrustedProxies::new(&self.trusted_proxies)
+    }
+
+    #[must_use]
+    pub fn janitor_settings(&self) -> JanitorSettings {
+        JanitorSettings {
+            expired_shares: self.on_share_expired,
+            orphan_grace_secs: self.orphan_grace_secs,
+            purge_orphan_uploads: self.purge_orphan_uploads,
+        }
+    }
+
+    /// Link a visitor opens to download `slug`, if a public URL is configured
+    #[must_use]
+    pub fn share_url(&self, slug: &str) -> Option<String> {
+        self.public_base_url
+            .as_ref()
+            .map(|base| format!("{}/{slug}", base.trim_end_matches('/')))
+    }
+}
@@ -1,9 +1,186 @@
 // src/db.rs
 use argon2::password_hash::{Error as PwHashError, PasswordHash, PasswordVerifier, SaltString};
 use argon2::{Argon2, PasswordHasher};
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
 use rand_core::OsRng;
-use rusqlite::{params, Connection, OptionalExtension};
-use serde::{Deserialize, Serialize};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::archive::{self, DirStats};
+use crate::error::ServiceError;
+use crate::hashing;
+use crate::migrations;
+use crate::policy::{ExpiredSharePolicy, FileChangePolicy, LockoutPolicy, PathPolicy};
+use crate::signing::SigningKey;
+use crate::slug::{self, SlugAlphabet};
+
+/// What a `FileEntry` points at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    #[default]
+    File,
+    /// Served as an archive generated on the fly
+    Dir,
+}
+
+impl FileKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Dir => "dir",
+        }
+    }
+}
+
+impl ToSql for FileKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for FileKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "file" => Ok(Self::File),
+            "dir" => Ok(Self::Dir),
+            other => Err(FromSqlError::Other(
+                format!("unknown file kind {other:?}").into(),
+            )),
+        }
+    }
+}
+
+/// Why a file has no `sha256` yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashState {
+    /// Queued for or running on the hash workers
+    Pending,
+    /// The file couldn't be read, `Db::verify_file_digest` retries
+    Failed,
+}
+
+impl HashState {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql for HashState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for HashState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(Self::Pending),
+            "failed" => Ok(Self::Failed),
+            other => Err(FromSqlError::Other(
+                format!("unknown hash state {other:?}").into(),
+            )),
+        }
+    }
+}
+
+/// What a share hands out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareKind {
+    File,
+    Dir,
+    /// Several files under one link, see `CreateShareReq::abs_paths`
+    Bundle,
+}
+
+impl From<FileKind> for ShareKind {
+    fn from(kind: FileKind) -> Self {
+        match kind {
+            FileKind::File => Self::File,
+            FileKind::Dir => Self::Dir,
+        }
+    }
+}
+
+/// Most files one bundle may hold
+pub const MAX_BUNDLE_FILES: usize = 256;
+
+/// Name bundles are shown and archived under
+#[must_use]
+pub fn bundle_name(slug: &str) -> String {
+    format!("bundle-{slug}")
+}
+
+/// One file of a bundle, as shown to recipients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleFile {
+    pub name: String,
+    pub size_bytes: i64,
+    pub mtime: Option<i64>,
+    pub sha256: Option<String>,
+}
+
+impl From<&FileEntry> for BundleFile {
+    fn from(file: &FileEntry) -> Self {
+        Self {
+            name: file.name.clone(),
+            size_bytes: file.size_bytes,
+            mtime: file.mtime,
+            sha256: file.sha256.clone(),
+        }
+    }
+}
+
+/// One entry of a directory share's tree, see `Db::list_share_dir`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeEntry {
+    pub name: String,
+    /// Below the shared directory, `/` separated; what the tree and
+    /// download routes take
+    pub path: String,
+    pub kind: FileKind,
+    /// 0 for directories
+    pub size_bytes: i64,
+    pub mtime: Option<i64>,
+}
+
+impl From<archive::DirEntry> for TreeEntry {
+    fn from(entry: archive::DirEntry) -> Self {
+        Self {
+            name: entry
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            kind: if entry.is_dir {
+                FileKind::Dir
+            } else {
+                FileKind::File
+            },
+            size_bytes: i64::try_from(entry.size).unwrap_or(i64::MAX),
+            mtime: entry.mtime,
+            path: entry.name,
+        }
+    }
+}
+
+/// Listing of one directory in a directory share
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirListing {
+    /// Directory listed, empty for the shared directory itself
+    pub path: String,
+    pub entries: Vec<TreeEntry>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
@@ -11,15 +188,478 @@ pub struct FileEntry {
     pub abs_path: String,
     pub name: String,
     pub size_bytes: i64,
+    /// Unix seconds, `None` for files registered before it was recorded
+    pub mtime: Option<i64>,
+    /// Lowercase hex, `None` until hashed and always for directories
+    pub sha256: Option<String>,
+    /// Set while `sha256` is `None` because of a background hash
+    pub hash_state: Option<HashState>,
+    pub created_at: String,
+    pub kind: FileKind,
+    /// Files and subdirectories, directories only. `None` for trees too
+    /// big to count on registration until they're first archived, their
+    /// `size_bytes` is 0 until then.
+    pub entry_count: Option<i64>,
+}
+
+/// Columns `file_from_row` expects, in order
+const FILE_COLUMNS: &str =
+    "f.id, f.abs_path, f.name, f.size_bytes, f.mtime, f.sha256, f.hash_state, f.created_at, \
+    f.kind, f.entry_count";
+
+fn file_from_row(r: &rusqlite::Row) -> Result<FileEntry, rusqlite::Error> {
+    Ok(FileEntry {
+        id: r.get(0)?,
+        abs_path: r.get(1)?,
+        name: r.get(2)?,
+        size_bytes: r.get(3)?,
+        mtime: r.get(4)?,
+        sha256: r.get(5)?,
+        hash_state: r.get(6)?,
+        created_at: r.get(7)?,
+        kind: r.get(8)?,
+        entry_count: r.get(9)?,
+    })
+}
+
+/// Result of re-hashing a file against its stored digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestCheck {
+    pub file_id: String,
+    pub stored: Option<String>,
+    pub actual: String,
+    /// `None` if there was no stored digest to compare with (it is stored now)
+    pub matches: Option<bool>,
+}
+
+/// What `get_download_target` hands the download handler.
+/// For bundles the file fields describe the bundle as a whole and
+/// `members` holds its files.
+#[derive(Debug, Clone)]
+pub struct DownloadTarget {
+    pub file_id: String,
+    pub abs_path: String,
+    pub file_name: String,
+    pub size_bytes: i64,
+    pub mtime: Option<i64>,
+    pub sha256: Option<String>,
+    pub kind: ShareKind,
+    pub members: Vec<FileEntry>,
+    /// Download session this request belongs to
+    pub session: String,
+    /// False if the request resumed an existing session
+    pub counted: bool,
+    /// Bytes of the file reserved for this request in its session, the
+    /// part that isn't sent goes back with `Db::return_download_bytes`
+    pub reserved: i64,
+}
+
+impl DownloadTarget {
+    fn single(file: FileEntry, session: String, counted: bool, reserved: i64) -> Self {
+        Self {
+            file_id: file.id,
+            abs_path: file.abs_path,
+            file_name: file.name,
+            size_bytes: file.size_bytes,
+            mtime: file.mtime,
+            sha256: file.sha256,
+            kind: file.kind.into(),
+            members: Vec::new(),
+            session,
+            counted,
+            reserved,
+        }
+    }
+}
+
+/// How a request proves it may open a password protected share.
+/// A plain `&str` is taken as the password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential<'a> {
+    /// Nothing given, only opens shares without a password
+    None,
+    Password(&'a str),
+    /// Token from `Db::unlock_share`
+    Token(&'a str),
+    /// URL signature the caller already checked with `UrlSigner::verify`,
+    /// valid until `expires` (unix seconds)
+    SignedUrl {
+        expires: i64,
+    },
+}
+
+impl<'a> From<&'a str> for Credential<'a> {
+    fn from(password: &'a str) -> Self {
+        Self::Password(password)
+    }
+}
+
+/// What a download request brings besides its credential
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadRequest<'a> {
+    /// Download session id from the client's cookie
+    pub session: Option<&'a str>,
+    /// First byte the Range header asks for, 0 without one. Only requests
+    /// continuing past byte 0 can belong to an earlier download.
+    pub offset: u64,
+}
+
+/// How `Db::authorized_download` let a request in
+enum DownloadAccess {
+    /// The live download session named by the request's cookie
+    Session(String),
+    /// The credential, it holds until this unix time if it expires
+    Credential { until: Option<i64> },
+}
+
+/// Result of `Db::unlock_share`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareUnlock {
+    pub token: String,
+    pub expires_at: String,
+}
+
+/// Chunked upload in progress, see `Db::create_upload_session`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub file_name: String,
+    pub size_bytes: i64,
+    /// Offset the next chunk has to start at
+    pub received_bytes: i64,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+const UPLOAD_SESSION_COLUMNS: &str =
+    "id, file_name, size_bytes, received_bytes, expires_at, created_at";
+
+fn upload_session_from_row(r: &rusqlite::Row) -> Result<UploadSession, rusqlite::Error> {
+    Ok(UploadSession {
+        id: r.get(0)?,
+        file_name: r.get(1)?,
+        size_bytes: r.get(2)?,
+        received_bytes: r.get(3)?,
+        expires_at: r.get(4)?,
+        created_at: r.get(5)?,
+    })
+}
+
+/// What a password failure counter is kept for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockoutScope {
+    Slug,
+    Ip,
+}
+
+impl ToSql for LockoutScope {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Self::Slug => "slug",
+            Self::Ip => "ip",
+        }
+        .into())
+    }
+}
+
+impl FromSql for LockoutScope {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "slug" => Ok(Self::Slug),
+            "ip" => Ok(Self::Ip),
+            other => Err(FromSqlError::Other(
+                format!("unknown lockout scope {other:?}").into(),
+            )),
+        }
+    }
+}
+
+/// One counter bumped by `Db::reserve_password_attempt`, with what it
+/// takes to undo it
+#[derive(Debug)]
+struct PasswordAttempt {
+    scope: LockoutScope,
+    key: String,
+    prev_locked_until: Option<String>,
+    locked_until: Option<String>,
+}
+
+/// Failed password attempts against a slug or from one client IP
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordFailures {
+    pub scope: LockoutScope,
+    pub key: String,
+    pub failures: i64,
+    /// Set once the failures ran past `LockoutPolicy::free_attempts`
+    pub locked_until: Option<String>,
+    pub last_failed_at: String,
+}
+
+/// How a download request ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadOutcome {
+    /// The whole response body was sent
+    Success,
+    /// The client went away before the body was sent
+    Aborted,
+    BadPassword,
+    Expired,
+    LimitReached,
+    NotFound,
+    /// Bad signature or a password lockout
+    Denied,
+    RateLimited,
+    Error,
+}
+
+impl DownloadOutcome {
+    const ALL: [Self; 9] = [
+        Self::Success,
+        Self::Aborted,
+        Self::BadPassword,
+        Self::Expired,
+        Self::LimitReached,
+        Self::NotFound,
+        Self::Denied,
+        Self::RateLimited,
+        Self::Error,
+    ];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Aborted => "aborted",
+            Self::BadPassword => "bad_password",
+            Self::Expired => "expired",
+            Self::LimitReached => "limit_reached",
+            Self::NotFound => "not_found",
+            Self::Denied => "denied",
+            Self::RateLimited => "rate_limited",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl From<&ServiceError> for DownloadOutcome {
+    fn from(e: &ServiceError) -> Self {
+        match e {
+            ServiceError::BadPassword => Self::BadPassword,
+            ServiceError::Expired => Self::Expired,
+            ServiceError::LimitReached => Self::LimitReached,
+            ServiceError::NotFound | ServiceError::FileMissing => Self::NotFound,
+            ServiceError::BadSignature(_) | ServiceError::TooManyAttempts { .. } => Self::Denied,
+            ServiceError::RateLimited { .. } => Self::RateLimited,
+            _ => Self::Error,
+        }
+    }
+}
+
+impl ToSql for DownloadOutcome {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for DownloadOutcome {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let value = value.as_str()?;
+        Self::ALL
+            .into_iter()
+            .find(|o| o.as_str() == value)
+            .ok_or_else(|| {
+                FromSqlError::Other(format!("unknown download outcome {value:?}").into())
+            })
+    }
+}
+
+/// A download request as the audit log records it
+#[derive(Debug, Clone, Default)]
+pub struct NewDownloadEvent {
+    pub slug: String,
+    /// The file served, `None` if the request failed before that
+    pub file_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes_sent: u64,
+    /// `Range` header as the client sent it
+    pub range: Option<String>,
+    pub outcome: Option<DownloadOutcome>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadEvent {
+    pub id: i64,
+    pub slug: String,
+    pub file_id: Option<String>,
+    pub at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes_sent: i64,
+    pub range: Option<String>,
+    pub outcome: DownloadOutcome,
+}
+
+/// Filters of the download audit log, all optional. `from` and `to` are
+/// dates SQLite understands, `to` is exclusive.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DownloadEventQuery {
+    pub slug: Option<String>,
+    pub file_id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Page of download events, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadEventPage {
+    pub events: Vec<DownloadEvent>,
+    /// Events matching the filters across all pages
+    pub total: i64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// Page size of the download and admin audit logs unless `limit` is given
+pub const DOWNLOAD_EVENTS_PAGE: u32 = 100;
+/// Largest `limit` accepted, also what one export holds at most
+pub const MAX_DOWNLOAD_EVENTS_PAGE: u32 = 10_000;
+
+/// A mutating admin call as the audit trail records it
+#[derive(Debug, Clone, Default)]
+pub struct NewAdminAudit {
+    /// Username of the admin, `None` if the call wasn't logged in
+    pub actor: Option<String>,
+    /// Method and route, e.g. `DELETE /admin/share/{slug}`
+    pub action: String,
+    pub target: Option<String>,
+    /// Request parameters as JSON, secrets already redacted
+    pub params: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    pub status: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminAuditEntry {
+    pub id: i64,
+    pub at: String,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub params: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    pub status: u16,
+}
+
+/// Filters of the admin audit trail, all optional. `q` matches anywhere
+/// in the target or params, `from` / `to` as in `DownloadEventQuery`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AdminAuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub q: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Page of the admin audit trail, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminAuditPage {
+    pub entries: Vec<AdminAuditEntry>,
+    pub total: i64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// Record of a share the janitor retired
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedShare {
+    pub id: i64,
+    pub slug: String,
+    /// Path of the shared file, the first one for a bundle
+    pub abs_path: String,
+    pub file_count: i64,
+    /// `expired` or `exhausted`
+    pub reason: String,
+    pub expires_at: Option<String>,
+    pub max_downloads: Option<i64>,
+    pub dl_count: i64,
     pub created_at: String,
+    pub archived_at: String,
 }
 
-#[derive(Deserialize)]
+/// Either `abs_path` or, for a bundle, `abs_paths` is set
+#[derive(Default, Serialize, Deserialize)]
 pub struct CreateShareReq {
-    abs_path: String,
-    password: Option<String>,
-    expires_at: Option<String>,
-    max_downloads: Option<i64>,
+    #[serde(default)]
+    pub abs_path: String,
+    #[serde(default)]
+    pub abs_paths: Vec<String>,
+    /// Custom slug, generated if not given, see `slug::validate_custom`
+    pub slug: Option<String>,
+    pub password: Option<String>,
+    pub expires_at: Option<String>,
+    pub max_downloads: Option<i64>,
+    /// Overrides the server's concurrent downloads per share
+    pub max_concurrent: Option<i64>,
+    /// Overrides the server's bandwidth cap per share
+    pub bytes_per_sec: Option<i64>,
+}
+
+/// Tells a missing field (`None`) from an explicit `null` (`Some(None)`)
+fn explicit_null<'de, T, D>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
+/// Partial update of a share. Fields left out stay as they are, `null`
+/// clears a limit or removes the password.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateShareReq {
+    #[serde(
+        default,
+        deserialize_with = "explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub password: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expires_at: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_downloads: Option<Option<i64>>,
+    /// Starts counting downloads from zero again
+    #[serde(default)]
+    pub reset_dl_count: bool,
+    #[serde(
+        default,
+        deserialize_with = "explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_concurrent: Option<Option<i64>>,
+    #[serde(
+        default,
+        deserialize_with = "explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bytes_per_sec: Option<Option<i64>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +672,28 @@ pub struct Share {
     #[serde(skip_serializing)]
     pub password_hash: Option<String>,
     pub created_at: String,
+    /// Concurrent downloads allowed, `None` for the server default
+    pub max_concurrent: Option<i64>,
+    /// Bandwidth of all downloads together, `None` for the server default
+    pub bytes_per_sec: Option<i64>,
+}
+
+/// Columns `share_from_row` expects, in order
+const SHARE_COLUMNS: &str = "slug, file_id, expires_at, max_downloads, dl_count, password_hash, \
+    created_at, max_concurrent, bytes_per_sec";
+
+fn share_from_row(r: &rusqlite::Row) -> Result<Share, rusqlite::Error> {
+    Ok(Share {
+        slug: r.get(0)?,
+        file_id: r.get(1)?,
+        expires_at: r.get(2)?,
+        max_downloads: r.get(3)?,
+        dl_count: r.get(4)?,
+        password_hash: r.get(5)?,
+        created_at: r.get(6)?,
+        max_concurrent: r.get(7)?,
+        bytes_per_sec: r.get(8)?,
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,16 +706,58 @@ pub struct PublicShare {
     pub max_downloads: Option<i64>,
     pub expires_at: Option<String>,
     pub password_required: bool,
+    /// SHA-256 of the file so recipients can verify it, `None` while pending
+    pub sha256: Option<String>,
+    pub kind: ShareKind,
+    /// For directories: files and subdirectories in the archive,
+    /// for bundles: number of files
+    pub entry_count: Option<i64>,
+    /// Files of a bundle, each downloadable on its own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Admin {
+    pub id: i64,
+    pub username: String,
 }
 
-const SLUG_SIZE: usize = 8;
-fn gen_slug(len: usize) -> String {
-    use rand::{distr::Alphanumeric, Rng};
-    rand::rng()
-        .sample_iter(&Alphanumeric)
-        .take(len)
-        .map(char::from)
-        .collect()
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminSession {
+    pub token: String,
+    pub expires_at: String,
+}
+
+/// How long an admin login stays valid
+const SESSION_HOURS: i64 = 12;
+/// How long a client can keep resuming one download without it counting again
+pub const DOWNLOAD_SESSION_HOURS: i64 = 6;
+/// Lifetime of the tokens `Db::unlock_share` hands out
+pub const UNLOCK_TOKEN_MINUTES: i64 = 15;
+/// How long an upload session survives without receiving data
+pub const UPLOAD_SESSION_HOURS: i64 = 24;
+const SESSION_TOKEN_BYTES: usize = 32;
+
+/// Default length of generated slugs
+pub const SLUG_SIZE: usize = 8;
+
+/// Modification time in unix seconds, if the platform has one
+pub(crate) fn unix_mtime(metadata: &std::fs::Metadata) -> Option<i64> {
+    let since_epoch = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    i64::try_from(since_epoch.as_secs()).ok()
+}
+
+fn gen_session_token() -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use rand_core::RngCore;
+    let mut bytes = [0u8; SESSION_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
 }
 
 // ————— Password Hashing —————
@@ -85,152 +789,545 @@ pub fn verify_password(password: &str, hash: &str) -> bool {
     }
 }
 
-#[derive(Debug)]
+/// Verified against on unknown usernames, so a miss costs as much as a
+/// wrong password and doesn't give away which admins exist
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("not a password").unwrap_or_default())
+}
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// Connections are per-connection state, so these run on every new pooled one
+fn init_connection(con: &mut Connection) -> Result<(), rusqlite::Error> {
+    // Tells the DB to enforce FK rules
+    con.pragma_update(None, "foreign_keys", true)?;
+    // Writers wait on each other instead of failing with SQLITE_BUSY
+    con.busy_timeout(std::time::Duration::from_secs(5))
+}
+
+/// Cheap to clone: clones share the same connection pool.
+/// Build it once at startup and hand it out through `web::Data`.
+#[derive(Debug, Clone)]
 pub struct Db {
-    con: Connection,
+    pool: Pool,
+    policy: PathPolicy,
+    on_change: FileChangePolicy,
+    lockout: LockoutPolicy,
+    slug_len: usize,
+    slug_alphabet: SlugAlphabet,
 }
 
 impl Db {
+    /// Opens (or creates) the DB file at `path` and migrates it to the latest schema
+    ///
     /// # Errors
     ///
-    /// Failing to write to the file
-    pub fn new() -> Result<Self, rusqlite::Error> {
-        let con = Connection::open("data.db")?;
-        // Tells the DB to enforce FK rules
-        con.pragma_update(None, "foreign_keys", true)?;
-
-        // Tables
-        con.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS file (
-                id          TEXT PRIMARY KEY,
-                abs_path    TEXT NOT NULL UNIQUE,
-                name        TEXT NOT NULL,
-                size_bytes  INTEGER NOT NULL,
-                created_at  TEXT NOT NULL DEFAULT (datetime('now'))
-            );
-
-            CREATE TABLE IF NOT EXISTS share (
-                slug            TEXT PRIMARY KEY,
-                file_id         TEXT NOT NULL REFERENCES file(id) ON DELETE CASCADE,
-                expires_at      TEXT,
-                max_downloads   INTEGER,
-                dl_count        INTEGER NOT NULL DEFAULT 0,
-                password_hash   TEXT,
-                created_at      TEXT NOT NULL DEFAULT (datetime('now'))
-            );",
-        )?;
+    /// Failing to open or write to the file,
+    /// `SchemaTooNew` if the file comes from a newer build
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ServiceError> {
+        let manager = SqliteConnectionManager::file(path).with_init(init_connection);
+        let pool = r2d2::Pool::new(manager)?;
 
-        Ok(Self { con })
+        // WAL lets readers carry on while a download bumps a counter
+        pool.get()?.pragma_update(None, "journal_mode", "WAL")?;
+        Self::from_pool(pool)
     }
 
-    // ————— file CRUD —————
-
+    /// Private DB that lives as long as this `Db` and its clones, for tests.
+    /// Pooled connections share it through SQLite's shared cache.
+    ///
     /// # Errors
     ///
-    /// Will error if failing to resolve path,
-    /// lacking permissions to access file's metadata,
-    /// unable to write to db
-    /// Will also fail if the file size is so large it exceeds u32 or i64
-    pub fn create_or_get_file(&self, abs_path: &str) -> Result<FileEntry, rusqlite::Error> {
-        use std::fs;
+    /// Failing to set up the schema
+    pub fn new_in_memory() -> Result<Self, ServiceError> {
+        let uri = format!("file:mem-{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+        let manager = SqliteConnectionManager::file(uri).with_init(init_connection);
+        // Idle connections are never reaped (no min_idle), so the DB stays alive
+        let pool = r2d2::Pool::new(manager)?;
+        Self::from_pool(pool)
+    }
 
-        // Canonicalize the path (e.g., resolve ./foo/../bar)
-        let canonical = fs::canonicalize(abs_path).map_err(|_| rusqlite::Error::InvalidQuery)?; // you can improve this error later
+    fn from_pool(pool: Pool) -> Result<Self, ServiceError> {
+        let mut con = pool.get()?;
+        migrations::migrate(&mut con)?;
+        drop(con);
+        // Hashed now, not by the first unknown-user login
+        dummy_hash();
 
-        let abs = canonical.to_string_lossy();
+        Ok(Self {
+            pool,
+            policy: PathPolicy::default(),
+            on_change: FileChangePolicy::default(),
+            lockout: LockoutPolicy::default(),
+            slug_len: SLUG_SIZE,
+            slug_alphabet: SlugAlphabet::default(),
+        })
+    }
 
-        // Check if the file is already in the DB
-        if let Some(existing) = self.get_file_by_path(&abs)? {
-            return Ok(existing);
-        }
+    fn con(&self) -> Result<PooledConnection<SqliteConnectionManager>, ServiceError> {
+        Ok(self.pool.get()?)
+    }
 
-        // Get metadata for insert
-        let metadata = fs::metadata(&canonical).map_err(|_| rusqlite::Error::InvalidQuery)?;
-        let name = canonical
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unnamed")
-            .to_string();
-        let size_u = metadata.len();
-        let size_bytes = i64::try_from(size_u).map_err(|_| rusqlite::Error::InvalidQuery)?; // This file is WAY too large
-        let id = uuid::Uuid::new_v4().to_string();
+    /// Runs `f` on actix's blocking thread pool, so SQLite and Argon2 work
+    /// doesn't stall the async workers
+    ///
+    /// # Errors
+    ///
+    /// Whatever `f` returns, or `Blocking` if the thread pool is gone
+    pub async fn blocking<T, F>(&self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(&Self) -> Result<T, ServiceError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        actix_web::web::block(move || f(&db)).await?
+    }
 
-        self.con.execute(
-            "INSERT INTO file (id, abs_path, name, size_bytes)
-         VALUES (?1, ?2, ?3, ?4)",
-            params![id, abs.as_ref(), name, size_bytes],
-        )?;
+    /// Restricts which paths `create_or_get_file` accepts
+    #[must_use]
+    pub fn with_path_policy(mut self, policy: PathPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        // Get created_at so the struct is complete
-        let created_at: String = self.con.query_row(
-            "SELECT created_at FROM file WHERE id = ?1",
-            params![id],
-            |row| row.get(0),
-        )?;
+    /// What downloads do when a file changed on disk, `Refresh` by default
+    #[must_use]
+    pub fn with_change_policy(mut self, on_change: FileChangePolicy) -> Self {
+        self.on_change = on_change;
+        self
+    }
 
-        Ok(FileEntry {
-            id,
-            abs_path: abs.into_owned(),
-            name,
-            size_bytes,
-            created_at,
-        })
+    /// When failed share passwords lock out, see `guard_password`
+    #[must_use]
+    pub fn with_lockout_policy(mut self, lockout: LockoutPolicy) -> Self {
+        self.lockout = lockout;
+        self
     }
 
-    /// # Errors
-    ///
-    /// erroring only if no file or filaure to unpack data
-    pub fn get_file_by_path(&self, abs_path: &str) -> Result<Option<FileEntry>, rusqlite::Error> {
-        self.con
-            .query_row(
-                "SELECT id, abs_path, name, size_bytes, created_at FROM file WHERE abs_path = ?1",
-                params![abs_path],
-                |r| {
-                    Ok(FileEntry {
-                        id: r.get(0)?,
-                        abs_path: r.get(1)?,
-                        name: r.get(2)?,
-                        size_bytes: r.get(3)?,
-                        created_at: r.get(4)?,
-                    })
-                },
-            )
-            .optional()
+    /// Length of generated share slugs, `SLUG_SIZE` by default
+    #[must_use]
+    pub fn with_slug_length(mut self, len: usize) -> Self {
+        self.slug_len = len;
+        self
     }
 
-    /// # Errors
-    ///
-    /// Will error if unable to delete file or file doesn't exist
-    /// FK inside shares is set to CASCADE, so no error there
-    pub fn delete_file(&self, file_id: &str) -> Result<bool, rusqlite::Error> {
-        let changed = self
-            .con
-            .execute("DELETE FROM file WHERE id = ?1", params![file_id])?;
-        Ok(changed > 0)
+    /// What generated share slugs are made of, `Alphanumeric` by default
+    #[must_use]
+    pub fn with_slug_alphabet(mut self, alphabet: SlugAlphabet) -> Self {
+        self.slug_alphabet = alphabet;
+        self
     }
 
-    // ————— share CRUD (Admin) —————
+    // ————— admin accounts —————
 
     /// # Errors
     ///
     /// Fails only with generic db failure to read
-    pub fn list_shares(&self) -> Result<Vec<Share>, rusqlite::Error> {
-        let mut stmt = self.con.prepare(
-            "SELECT slug, file_id, expires_at, max_downloads, dl_count, password_hash, created_at
-             FROM share ORDER BY created_at DESC",
+    pub fn has_admin(&self) -> Result<bool, ServiceError> {
+        Ok(self
+            .con()?
+            .query_one("SELECT EXISTS(SELECT 1 FROM admin)", [], |r| r.get(0))?)
+    }
+
+    /// # Errors
+    ///
+    /// Will error if the username is taken or the password can't be hashed
+    pub fn create_admin(&self, username: &str, password: &str) -> Result<Admin, ServiceError> {
+        let con = self.con()?;
+        let hash = hash_password(password).map_err(|_| ServiceError::Hash)?;
+        con.execute(
+            "INSERT INTO admin (username, password_hash) VALUES (?1, ?2)",
+            params![username, hash],
         )?;
-        let rows = stmt.query_map([], |r| {
-            Ok(Share {
-                slug: r.get(0)?,
-                file_id: r.get(1)?,
-                expires_at: r.get(2)?,
-                max_downloads: r.get(3)?,
-                dl_count: r.get(4)?,
-                password_hash: r.get(5)?,
-                created_at: r.get(6)?,
-            })
-        })?;
+        Ok(Admin {
+            id: con.last_insert_rowid(),
+            username: username.to_string(),
+        })
+    }
+
+    /// Checks the credentials and opens a new session.
+    /// Returns `Ok(None)` on unknown user or wrong password
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure
+    pub fn login(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<AdminSession>, ServiceError> {
+        let con = self.con()?;
+        let admin: Option<(i64, String)> = con
+            .query_row(
+                "SELECT id, password_hash FROM admin WHERE username = ?1",
+                params![username],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()?;
+
+        let Some((admin_id, hash)) = admin else {
+            let _ = verify_password(password, dummy_hash());
+            return Ok(None);
+        };
+        if !verify_password(password, &hash) {
+            return Ok(None);
+        }
+
+        let token = gen_session_token();
+        let expires_at: String = con.query_one(
+            "INSERT INTO admin_session (token, admin_id, expires_at)
+            VALUES (?1, ?2, datetime('now', ?3))
+            RETURNING expires_at",
+            params![token, admin_id, format!("+{SESSION_HOURS} hours")],
+            |r| r.get(0),
+        )?;
+
+        Ok(Some(AdminSession { token, expires_at }))
+    }
+
+    /// Looks up the admin owning a live session token
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn get_session_admin(&self, token: &str) -> Result<Option<Admin>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            "SELECT a.id, a.username
+                FROM admin_session s
+                JOIN admin a ON s.admin_id = a.id
+                WHERE s.token = ?1 AND s.expires_at > datetime('now')",
+            params![token],
+            |r| {
+                Ok(Admin {
+                    id: r.get(0)?,
+                    username: r.get(1)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// Ends a session, also sweeping any expired ones
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn delete_session(&self, token: &str) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        let changed = con.execute("DELETE FROM admin_session WHERE token = ?1", params![token])?;
+        con.execute(
+            "DELETE FROM admin_session WHERE expires_at <= datetime('now')",
+            [],
+        )?;
+        Ok(changed > 0)
+    }
+
+    // ————— file CRUD —————
+
+    /// # Errors
+    ///
+    /// `NotFound` if the path doesn't exist,
+    /// `PathRejected` if the `PathPolicy` doesn't allow it,
+    /// `Io` if lacking permissions to access file's metadata,
+    /// `Invalid` if the file size is so large it exceeds i64
+    /// other than that, unable to write to db
+    pub fn create_or_get_file(&self, abs_path: &str) -> Result<FileEntry, ServiceError> {
+        use std::fs;
+
+        // Canonicalize the path (e.g., resolve ./foo/../bar)
+        let canonical = fs::canonicalize(abs_path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ServiceError::NotFound,
+            _ => ServiceError::Io(e),
+        })?;
+
+        // Checked on the resolved path so symlinks can't escape the roots
+        if !self.policy.allows(&canonical) {
+            return Err(ServiceError::PathRejected(canonical));
+        }
+
+        let abs = canonical.to_string_lossy();
+        let metadata = fs::metadata(&canonical)?;
+
+        // Check if the file is already in the DB
+        if let Some(existing) = self.get_file_by_path(&abs)? {
+            // Registering a directory again is how its totals get rescanned
+            if existing.kind == FileKind::Dir {
+                return self.rescan_dir(existing);
+            }
+            return Ok(existing);
+        }
+
+        // Get metadata for insert
+        let name = canonical
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unnamed")
+            .to_string();
+        let (kind, size_bytes, entry_count) = if metadata.is_dir() {
+            let stats = archive::dir_stats(&canonical, &self.policy);
+            (
+                FileKind::Dir,
+                stats.map_or(0, |s| s.total_bytes),
+                stats.map(|s| s.entries),
+            )
+        } else {
+            let size_bytes = i64::try_from(metadata.len())
+                .map_err(|_| ServiceError::Invalid("file too large".into()))?; // This file is WAY too large
+            (FileKind::File, size_bytes, None)
+        };
+        let mtime = unix_mtime(&metadata);
+        let id = uuid::Uuid::new_v4().to_string();
+
+        let con = self.con()?;
+        con.execute(
+            "INSERT INTO file (id, abs_path, name, size_bytes, mtime, kind, entry_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![id, abs.as_ref(), name, size_bytes, mtime, kind, entry_count],
+        )?;
+
+        // Get created_at so the struct is complete
+        let created_at: String = con.query_row(
+            "SELECT created_at FROM file WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        drop(con);
+
+        let mut file = FileEntry {
+            id,
+            abs_path: abs.into_owned(),
+            name,
+            size_bytes,
+            mtime,
+            sha256: None,
+            hash_state: None,
+            created_at,
+            kind,
+            entry_count,
+        };
+        self.hash_or_schedule(&mut file);
+        Ok(file)
+    }
+
+    fn rescan_dir(&self, dir: FileEntry) -> Result<FileEntry, ServiceError> {
+        // Too big to count here, the totals of its last archive stay
+        let Some(stats) = archive::dir_stats(Path::new(&dir.abs_path), &self.policy) else {
+            return Ok(dir);
+        };
+        self.set_dir_stats(&dir.id, stats)?;
+        Ok(FileEntry {
+            size_bytes: stats.total_bytes,
+            entry_count: Some(stats.entries),
+            ..dir
+        })
+    }
+
+    /// Records the totals of a directory, e.g. counted while archiving it
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn set_dir_stats(&self, file_id: &str, stats: DirStats) -> Result<(), ServiceError> {
+        self.con()?.execute(
+            "UPDATE file SET size_bytes = ?1, entry_count = ?2 WHERE id = ?3 AND kind = 'dir'",
+            params![stats.total_bytes, stats.entries, file_id],
+        )?;
+        Ok(())
+    }
+
+    /// Policy directory archives are filtered with
+    #[must_use]
+    pub fn path_policy(&self) -> &PathPolicy {
+        &self.policy
+    }
+
+    /// Small files are hashed right away, big ones queued for the hash
+    /// workers so registering doesn't wait on reading gigabytes
+    fn hash_or_schedule(&self, file: &mut FileEntry) {
+        if file.kind == FileKind::Dir {
+            return;
+        }
+        if file.size_bytes <= hashing::INLINE_HASH_LIMIT {
+            match self.hash_file(file) {
+                Ok(digest) => file.sha256 = digest,
+                Err(e) => {
+                    log::warn!("hashing {} failed: {e}", file.abs_path);
+                    self.set_hash_state(file, HashState::Failed);
+                }
+            }
+            return;
+        }
+        // Pending before it's queued, so a quick worker can't be overwritten
+        self.set_hash_state(file, HashState::Pending);
+        if let Err(e) = self.queue_hash_job(file.clone()) {
+            log::warn!("could not queue hash job: {e}");
+            self.set_hash_state(file, HashState::Failed);
+        }
+    }
+
+    fn queue_hash_job(&self, mut file: FileEntry) -> std::io::Result<()> {
+        let db = self.clone();
+        hashing::queue_job(move || match db.hash_file(&file) {
+            Ok(Some(_)) => log::info!("hashed {}", file.abs_path),
+            Ok(None) => log::info!("{} changed while hashing, skipped", file.abs_path),
+            Err(e) => {
+                log::warn!("hashing {} failed: {e}", file.abs_path);
+                db.set_hash_state(&mut file, HashState::Failed);
+            }
+        })
+    }
+
+    /// Records why `file` has no digest, unless the row moved on to other
+    /// content meanwhile. A failed write is only logged.
+    fn set_hash_state(&self, file: &mut FileEntry, state: HashState) {
+        file.hash_state = Some(state);
+        let res = self.con().and_then(|con| {
+            con.execute(
+                "UPDATE file SET hash_state = ?1
+                WHERE id = ?2 AND size_bytes = ?3 AND mtime IS ?4 AND sha256 IS NULL",
+                params![state, file.id, file.size_bytes, file.mtime],
+            )
+            .map_err(ServiceError::from)
+        });
+        if let Err(e) = res {
+            log::warn!("could not record hash state of {}: {e}", file.abs_path);
+        }
+    }
+
+    /// Queues the hash jobs that were pending when the server last stopped
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn resume_hash_jobs(&self) -> Result<usize, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(&format!(
+            "SELECT {FILE_COLUMNS} FROM file f WHERE f.hash_state = 'pending'"
+        ))?;
+        let files = stmt
+            .query_map([], file_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(stmt);
+        drop(con);
+
+        let count = files.len();
+        for file in files {
+            if let Err(e) = self.queue_hash_job(file) {
+                log::warn!("could not queue hash job: {e}");
+            }
+        }
+        Ok(count)
+    }
+
+    /// Hashes the file and stores the digest, but only if the row still has
+    /// the size / mtime the hash started from. Returns the stored digest.
+    ///
+    /// # Errors
+    ///
+    /// Will error if the file can't be read or the db written
+    pub fn hash_file(&self, file: &FileEntry) -> Result<Option<String>, ServiceError> {
+        let digest = hashing::sha256_file(Path::new(&file.abs_path))?;
+        let changed = self.con()?.execute(
+            "UPDATE file SET sha256 = ?1, hashed_at = datetime('now'), hash_state = NULL
+            WHERE id = ?2 AND size_bytes = ?3 AND mtime IS ?4",
+            params![digest, file.id, file.size_bytes, file.mtime],
+        )?;
+        Ok((changed > 0).then_some(digest))
+    }
+
+    /// Re-hashes a file now and compares with the stored digest
+    ///
+    /// # Errors
+    ///
+    /// `NotFound` for an unknown id, `FileMissing` if it's gone from disk,
+    /// otherwise read / db failures
+    pub fn verify_file_digest(&self, file_id: &str) -> Result<DigestCheck, ServiceError> {
+        let file = self.get_file(file_id)?.ok_or(ServiceError::NotFound)?;
+        if file.kind == FileKind::Dir {
+            return Err(ServiceError::Invalid("directories have no digest".into()));
+        }
+        let actual =
+            hashing::sha256_file(Path::new(&file.abs_path)).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => ServiceError::FileMissing,
+                _ => ServiceError::Io(e),
+            })?;
+
+        let matches = match &file.sha256 {
+            Some(stored) => Some(*stored == actual),
+            None => {
+                self.con()?.execute(
+                    "UPDATE file SET sha256 = ?1, hashed_at = datetime('now'), hash_state = NULL
+                    WHERE id = ?2",
+                    params![actual, file.id],
+                )?;
+                None
+            }
+        };
+        if matches == Some(false) {
+            log::warn!("digest mismatch for {}", file.abs_path);
+        }
+
+        Ok(DigestCheck {
+            file_id: file.id,
+            stored: file.sha256,
+            actual,
+            matches,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn get_file(&self, file_id: &str) -> Result<Option<FileEntry>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            &format!("SELECT {FILE_COLUMNS} FROM file f WHERE f.id = ?1"),
+            params![file_id],
+            file_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// # Errors
+    ///
+    /// erroring only if no file or filaure to unpack data
+    pub fn get_file_by_path(&self, abs_path: &str) -> Result<Option<FileEntry>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            &format!("SELECT {FILE_COLUMNS} FROM file f WHERE f.abs_path = ?1"),
+            params![abs_path],
+            file_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// # Errors
+    ///
+    /// Will error if unable to delete file or file doesn't exist
+    /// FK inside shares is set to CASCADE, so no error there
+    pub fn delete_file(&self, file_id: &str) -> Result<bool, ServiceError> {
+        let changed = self
+            .con()?
+            .execute("DELETE FROM file WHERE id = ?1", params![file_id])?;
+        Ok(changed > 0)
+    }
+
+    // ————— share CRUD (Admin) —————
+
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn list_shares(&self) -> Result<Vec<Share>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(&format!(
+            "SELECT {SHARE_COLUMNS} FROM share ORDER BY created_at DESC"
+        ))?;
+        let rows = stmt.query_map([], share_from_row)?;
 
         let mut out = Vec::new();
         for row in rows {
@@ -242,87 +1339,322 @@ impl Db {
     /// # Errors
     ///
     /// Returning errors if data can't be unpacked or share doesn't exist
-    pub fn get_share(&self, slug: &str) -> Result<Option<Share>, rusqlite::Error> {
-        self.con
-            .query_one(
-                "
-            SELECT slug, file_id, expires_at, max_downloads, dl_count, password_hash, created_at
-            FROM share WHERE slug = ?1",
-                params![slug],
-                |r| {
-                    Ok(Share {
-                        slug: r.get(0)?,
-                        file_id: r.get(1)?,
-                        expires_at: r.get(2)?,
-                        max_downloads: r.get(3)?,
-                        dl_count: r.get(4)?,
-                        password_hash: r.get(5)?,
-                        created_at: r.get(6)?,
-                    })
-                },
-            )
-            .optional()
+    pub fn get_share(&self, slug: &str) -> Result<Option<Share>, ServiceError> {
+        let con = self.con()?;
+        con.query_one(
+            &format!("SELECT {SHARE_COLUMNS} FROM share WHERE slug = ?1"),
+            params![slug],
+            share_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
     }
 
     /// # Errors
     ///
-    /// Can fail if random generation of slugs fails 5 times
+    /// `SlugCollision` if random generation of slugs fails 5 times,
+    /// `SlugTaken` if the custom slug is in use in any case,
+    /// `Invalid` if the custom slug isn't valid,
+    /// `expires_at` isn't a date, `max_downloads` is below 1
+    /// or a throttle override isn't positive,
+    /// or the paths don't make a valid share (see `share_paths`),
     /// other than that, simple read-write server issues or missing file
-    pub fn create_share(&self, new_share: &CreateShareReq) -> Result<Share, rusqlite::Error> {
+    pub fn create_share(&self, new_share: &CreateShareReq) -> Result<Share, ServiceError> {
+        self.validate_limits(new_share.expires_at.as_deref(), new_share.max_downloads)?;
+        Self::validate_throttle(new_share.max_concurrent, new_share.bytes_per_sec)?;
+        let paths = Self::share_paths(new_share)?;
+        if let Some(slug) = &new_share.slug {
+            slug::validate_custom(slug)?;
+        }
+
         // If there is a password, attempt to hash it
         let hashed_password: Option<String> = match &new_share.password {
-            Some(pw) => Some(hash_password(pw).map_err(|_| rusqlite::Error::InvalidQuery)?),
+            Some(pw) => Some(hash_password(pw).map_err(|_| ServiceError::Hash)?),
             None => None,
         };
-        // Check if file exists, if not, create one!
-        let file = self.create_or_get_file(&new_share.abs_path)?;
-
-        let mut slug = gen_slug(SLUG_SIZE);
-        let mut attempts = 0;
-        // check for slug on DB
-        loop {
-            let slug_exists: bool = self.con.query_one(
-                "SELECT EXISTS(SELECT 1 FROM share WHERE slug = ?1)",
+        // Check if files exist, if not, create them!
+        let files = paths
+            .iter()
+            .map(|p| self.create_or_get_file(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        if files.len() > 1 {
+            Self::validate_bundle(&files)?;
+        }
+
+        let mut con = self.con()?;
+        // Immediate, so a custom slug can't be taken between check and insert.
+        // Compared without case, like the reserved slugs are.
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let slug_exists = |slug: &str| -> rusqlite::Result<bool> {
+            tx.query_one(
+                "SELECT EXISTS(SELECT 1 FROM share WHERE slug = ?1 COLLATE NOCASE)",
                 params![slug],
                 |r| r.get(0),
-            )?;
-            if !slug_exists {
-                break;
+            )
+        };
+        let slug = if let Some(custom) = &new_share.slug {
+            if slug_exists(custom)? {
+                return Err(ServiceError::SlugTaken(custom.clone()));
             }
-            slug = gen_slug(SLUG_SIZE);
-            attempts += 1;
+            custom.clone()
+        } else {
+            let mut slug = self.slug_alphabet.generate(self.slug_len);
+            let mut attempts = 0;
+            // check for slug on DB
+            while slug_exists(&slug)? {
+                slug = self.slug_alphabet.generate(self.slug_len);
+                attempts += 1;
 
-            if attempts > 5 {
-                return Err(rusqlite::Error::ExecuteReturnedResults);
+                if attempts > 5 {
+                    return Err(ServiceError::SlugCollision);
+                }
             }
-        }
+            slug
+        };
 
         // Add to db
-
-        let res = self.con.execute(
-            "INSERT INTO share (slug, file_id, expires_at, max_downloads, password_hash)
-            VALUES (?1, ?2, ?3, ?4, ?5)",
+        tx.execute(
+            "INSERT INTO share (slug, file_id, expires_at, max_downloads, password_hash,
+                max_concurrent, bytes_per_sec)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![
                 slug,
-                file.id,
+                files[0].id,
                 new_share.expires_at,
                 new_share.max_downloads,
                 hashed_password,
+                new_share.max_concurrent,
+                new_share.bytes_per_sec,
             ],
-        );
+        )?;
+        for (position, file) in files.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO share_file (slug, file_id, position) VALUES (?1, ?2, ?3)",
+                params![slug, file.id, position as i64],
+            )?;
+        }
+        tx.commit()?;
+        drop(con);
+
+        self.get_share(&slug)?.ok_or(ServiceError::NotFound)
+    }
+
+    /// The paths a request shares: `abs_path`, or the `abs_paths` of a bundle
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` if both or neither are given, or the bundle is too big
+    fn share_paths(req: &CreateShareReq) -> Result<Vec<&str>, ServiceError> {
+        match (req.abs_path.is_empty(), req.abs_paths.is_empty()) {
+            (false, true) => Ok(vec![req.abs_path.as_str()]),
+            (true, false) if req.abs_paths.len() > MAX_BUNDLE_FILES => Err(ServiceError::Invalid(
+                format!("a bundle holds at most {MAX_BUNDLE_FILES} files"),
+            )),
+            (true, false) => Ok(req.abs_paths.iter().map(String::as_str).collect()),
+            (true, true) => Err(ServiceError::Invalid("abs_path is required".into())),
+            (false, false) => Err(ServiceError::Invalid(
+                "give either abs_path or abs_paths".into(),
+            )),
+        }
+    }
+
+    /// Bundle files are downloaded and archived by name, so names have to
+    /// be unique, and only regular files can be members
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` describing the first offending file
+    fn validate_bundle(files: &[FileEntry]) -> Result<(), ServiceError> {
+        let mut names = std::collections::HashSet::new();
+        for file in files {
+            if file.kind != FileKind::File {
+                return Err(ServiceError::Invalid(format!(
+                    "{} is a directory, bundles only hold files",
+                    file.name
+                )));
+            }
+            if !names.insert(file.name.as_str()) {
+                return Err(ServiceError::Invalid(format!(
+                    "more than one file named {}",
+                    file.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// SQLite has to be able to read `expires_at`, otherwise the share
+    /// would count as expired straight away
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` describing the first bad field
+    fn validate_limits(
+        &self,
+        expires_at: Option<&str>,
+        max_downloads: Option<i64>,
+    ) -> Result<(), ServiceError> {
+        if let Some(expires_at) = expires_at {
+            self.validate_date("expires_at", expires_at)?;
+        }
+        Self::validate_max_downloads(max_downloads)
+    }
+
+    /// # Errors
+    ///
+    /// `Invalid` if `max_downloads` is below 1, a share nobody can download
+    /// is an expired share
+    fn validate_max_downloads(max_downloads: Option<i64>) -> Result<(), ServiceError> {
+        if max_downloads.is_some_and(|m| m < 1) {
+            return Err(ServiceError::Invalid(
+                "max_downloads must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// `Invalid` naming `field` if SQLite can't read `date`
+    fn validate_date(&self, field: &str, date: &str) -> Result<(), ServiceError> {
+        let parsed: bool =
+            self.con()?
+                .query_one("SELECT julianday(?1) IS NOT NULL", params![date], |r| {
+                    r.get(0)
+                })?;
+        if !parsed {
+            return Err(ServiceError::Invalid(format!(
+                "{field} is not a date: {date}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Throttle overrides can only tighten or loosen a limit, not switch
+    /// downloads off
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` naming the first value below 1
+    fn validate_throttle(
+        max_concurrent: Option<i64>,
+        bytes_per_sec: Option<i64>,
+    ) -> Result<(), ServiceError> {
+        for (name, value) in [
+            ("max_concurrent", max_concurrent),
+            ("bytes_per_sec", bytes_per_sec),
+        ] {
+            if value.is_some_and(|v| v < 1) {
+                return Err(ServiceError::Invalid(format!("{name} must be at least 1")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the fields `changes` sets. A new or removed password also
+    /// ends the share's unlock tokens and download sessions, so access
+    /// granted under the old one doesn't carry over.
+    ///
+    /// # Errors
+    ///
+    /// `NotFound` for an unknown slug, `Invalid` for a change that fails
+    /// the checks of `create_share` or a `max_downloads` below the
+    /// downloads already made
+    pub fn update_share(
+        &self,
+        slug: &str,
+        changes: &UpdateShareReq,
+    ) -> Result<Share, ServiceError> {
+        if changes.password.is_none()
+            && changes.expires_at.is_none()
+            && changes.max_downloads.is_none()
+            && !changes.reset_dl_count
+            && changes.max_concurrent.is_none()
+            && changes.bytes_per_sec.is_none()
+        {
+            return Err(ServiceError::Invalid("no changes given".into()));
+        }
+        if let Some(Some(expires_at)) = &changes.expires_at {
+            self.validate_date("expires_at", expires_at)?;
+        }
+        Self::validate_max_downloads(changes.max_downloads.flatten())?;
+        Self::validate_throttle(
+            changes.max_concurrent.flatten(),
+            changes.bytes_per_sec.flatten(),
+        )?;
+        // Hashed up front, Argon2 is too slow to run inside the transaction
+        let password_hash = match &changes.password {
+            Some(Some(pw)) if pw.is_empty() => {
+                return Err(ServiceError::Invalid(
+                    "password can't be empty, send null to remove it".into(),
+                ));
+            }
+            Some(Some(pw)) => Some(Some(hash_password(pw).map_err(|_| ServiceError::Hash)?)),
+            Some(None) => Some(None),
+            None => None,
+        };
+
+        let mut con = self.con()?;
+        // Immediate, so no download is counted between the check and the update
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = tx
+            .query_one(
+                &format!("SELECT {SHARE_COLUMNS} FROM share WHERE slug = ?1"),
+                params![slug],
+                share_from_row,
+            )
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
 
-        match res {
-            Ok(_) => self.get_share(&slug)?.ok_or(rusqlite::Error::InvalidQuery),
-            Err(e) => Err(e),
+        let dl_count = if changes.reset_dl_count {
+            0
+        } else {
+            current.dl_count
+        };
+        let max_downloads = changes.max_downloads.unwrap_or(current.max_downloads);
+        if let Some(max) = max_downloads.filter(|max| *max < dl_count) {
+            return Err(ServiceError::Invalid(format!(
+                "max_downloads {max} is below the {dl_count} downloads already made, \
+                raise it or reset dl_count"
+            )));
+        }
+
+        tx.execute(
+            "UPDATE share SET expires_at = ?2, max_downloads = ?3, dl_count = ?4,
+                max_concurrent = ?5, bytes_per_sec = ?6
+            WHERE slug = ?1",
+            params![
+                slug,
+                changes.expires_at.clone().unwrap_or(current.expires_at),
+                max_downloads,
+                dl_count,
+                changes.max_concurrent.unwrap_or(current.max_concurrent),
+                changes.bytes_per_sec.unwrap_or(current.bytes_per_sec),
+            ],
+        )?;
+        if let Some(password_hash) = password_hash {
+            tx.execute(
+                "UPDATE share SET password_hash = ?2 WHERE slug = ?1",
+                params![slug, password_hash],
+            )?;
+            tx.execute("DELETE FROM share_unlock WHERE slug = ?1", params![slug])?;
+            tx.execute(
+                "DELETE FROM download_session WHERE slug = ?1",
+                params![slug],
+            )?;
         }
+        tx.commit()?;
+        drop(con);
+
+        self.get_share(slug)?.ok_or(ServiceError::NotFound)
     }
 
     /// # Errors
     ///
     /// Will error if unable to delete share or share doesn't exist
-    pub fn delete_share(&self, slug: &str) -> Result<bool, rusqlite::Error> {
+    pub fn delete_share(&self, slug: &str) -> Result<bool, ServiceError> {
         let changed = self
-            .con
+            .con()?
             .execute("DELETE FROM share WHERE slug = ?1", params![slug])?;
         Ok(changed > 0)
     }
@@ -332,8 +1664,19 @@ impl Db {
     /// # Errors
     ///
     /// if unable to unwrap variables or fetch from db
-    pub fn get_public_share(&self, slug: &str) -> Result<Option<PublicShare>, rusqlite::Error> {
-        self.con
+    /// `FileMissing` / `FileChanged` per `check_file_on_disk`
+    pub fn get_public_share(&self, slug: &str) -> Result<Option<PublicShare>, ServiceError> {
+        let files = self.get_bundle_files(slug)?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+        let files = files
+            .iter()
+            .map(|f| self.check_file_on_disk(f))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let con = self.con()?;
+        let public = con
             .query_row(
                 "
             SELECT
@@ -344,7 +1687,10 @@ impl Db {
                 s.dl_count,
                 s.max_downloads,
                 s.expires_at,
-                s.password_hash IS NOT NULL
+                s.password_hash IS NOT NULL,
+                f.sha256,
+                f.kind,
+                f.entry_count
             FROM share s
             JOIN file f ON s.file_id = f.id
             WHERE s.slug = ?1
@@ -360,82 +1706,1357 @@ impl Db {
                         max_downloads: r.get(5)?,
                         expires_at: r.get(6)?,
                         password_required: r.get(7)?,
+                        sha256: r.get(8)?,
+                        kind: r.get::<_, FileKind>(9)?.into(),
+                        entry_count: r.get(10)?,
+                        files: Vec::new(),
                     })
                 },
             )
-            .optional()
-    }
+            .optional()?;
 
-    /// # Errors
-    ///
-    /// Will error if the slug is false
-    fn check_password(&self, slug: &str, input: &str) -> Result<bool, rusqlite::Error> {
-        let share = self.get_share(slug)?.ok_or(rusqlite::Error::InvalidQuery)?;
+        Ok(public.map(|mut public| {
+            if files.len() > 1 {
+                public.kind = ShareKind::Bundle;
+                public.file_name = bundle_name(slug);
+                public.file_size = files.iter().map(|f| f.size_bytes).sum();
+                public.sha256 = None;
+                public.entry_count = Some(files.len() as i64);
+                public.files = files.iter().map(BundleFile::from).collect();
+            }
+            public
+        }))
+    }
 
+    /// Returns true if the share has no password or the input matches it
+    fn check_password(share: &Share, input: &str) -> bool {
         match &share.password_hash {
-            None => Ok(true),
-            Some(hash) => Ok(verify_password(input, hash)),
+            None => true,
+            Some(hash) => verify_password(input, hash),
         }
     }
 
-    /// #Errors
+    /// Bumps `dl_count` only if the share is still live (not expired, under
+    /// `max_downloads`). Check and increment are one statement, so concurrent
+    /// downloads can't go past the limit.
     ///
-    /// failure if slug is wrong or db unreachable
-    fn increase_dl(&self, slug: &str) -> Result<bool, rusqlite::Error> {
-        let dl_count: i64 = self
-            .con
-            .query_one(
-                "SELECT dl_count FROM share WHERE slug = ?1",
-                params![slug],
-                |r| r.get(0),
-            )
-            .optional()?
-            .unwrap_or(-1);
-
-        if dl_count < 0 {
-            return Err(rusqlite::Error::InvalidQuery);
+    /// # Errors
+    ///
+    /// failure if db unreachable
+    fn increase_dl(&self, slug: &str) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        let res = con.execute(
+            "UPDATE share SET dl_count = dl_count + 1
+            WHERE slug = ?1
+              AND (max_downloads IS NULL OR dl_count < max_downloads)
+              AND (expires_at IS NULL OR julianday(expires_at) > julianday('now'))",
+            params![slug],
+        )?;
+
+        Ok(res > 0)
+    }
+
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    fn is_expired(&self, slug: &str) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        Ok(con.query_one(
+            "SELECT expires_at IS NOT NULL AND julianday(expires_at) <= julianday('now')
+            FROM share WHERE slug = ?1",
+            params![slug],
+            |r| r.get(0),
+        )?)
+    }
+
+    /// Works out why `increase_dl` refused a share
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    fn gone_reason(&self, slug: &str) -> Result<ServiceError, ServiceError> {
+        Ok(if self.is_expired(slug)? {
+            ServiceError::Expired
+        } else {
+            ServiceError::LimitReached
+        })
+    }
+
+    /// True if `session` is a live download session for `slug`
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    fn has_download_session(&self, slug: &str, session: &str) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        Ok(con.query_one(
+            "SELECT EXISTS(
+                SELECT 1 FROM download_session
+                WHERE id = ?1 AND slug = ?2 AND expires_at > datetime('now'))",
+            params![session, slug],
+            |r| r.get(0),
+        )?)
+    }
+
+    /// Starts a download session, also sweeping expired ones.
+    /// It lasts `DOWNLOAD_SESSION_HOURS`, or until `until` (unix seconds)
+    /// if that's sooner, so it never outlives the credential it came from.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    fn create_download_session(
+        &self,
+        slug: &str,
+        until: Option<i64>,
+    ) -> Result<String, ServiceError> {
+        let con = self.con()?;
+        con.execute(
+            "DELETE FROM download_session WHERE expires_at <= datetime('now')",
+            [],
+        )?;
+        let id = gen_session_token();
+        con.execute(
+            "INSERT INTO download_session (id, slug, expires_at)
+            VALUES (?1, ?2, MIN(
+                datetime('now', ?3),
+                COALESCE(datetime(?4, 'unixepoch'), datetime('now', ?3))))",
+            params![id, slug, format!("+{DOWNLOAD_SESSION_HOURS} hours"), until],
+        )?;
+        Ok(id)
+    }
+
+    /// Checks access and counts the download. A request continuing a file
+    /// past byte 0 with the cookie of a live download session instead rides
+    /// that session, which stands in for the credential, until the session
+    /// was sent the whole file, see `reserve_download_bytes`.
+    /// Archives of bundles and directories have no ranges, they always count.
+    ///
+    /// # Errors
+    ///
+    /// `NotFound` if the slug doesn't exist,
+    /// `BadPassword` on failed auth,
+    /// `FileMissing` / `FileChanged` per `check_file_on_disk`,
+    /// `Expired` / `LimitReached` if the share is used up,
+    /// other than that, basic db failures
+    pub fn get_download_target<'a>(
+        &self,
+        slug: &str,
+        credential: impl Into<Credential<'a>>,
+        request: DownloadRequest<'_>,
+    ) -> Result<DownloadTarget, ServiceError> {
+        let credential = credential.into();
+        let files = self.get_bundle_files(slug)?;
+        let single = matches!(files.as_slice(), [file] if file.kind == FileKind::File);
+        let session = request.session.filter(|_| single && request.offset > 0);
+        let access = self.authorized_download(slug, credential, session)?;
+
+        // Before counting, so a broken file doesn't use up a download
+        let mut files = files
+            .iter()
+            .map(|f| self.check_file_on_disk(f))
+            .collect::<Result<Vec<_>, _>>()?;
+        if single {
+            let file = files.pop().ok_or(ServiceError::NotFound)?;
+            return self.start_download(slug, access, credential, file, request.offset);
+        }
+
+        let until = self.credential_until(slug, access, credential)?;
+        let session = self.count_download(slug, until)?;
+        if files.len() > 1 {
+            return Ok(DownloadTarget {
+                file_id: files[0].id.clone(),
+                abs_path: files[0].abs_path.clone(),
+                file_name: bundle_name(slug),
+                size_bytes: files.iter().map(|f| f.size_bytes).sum(),
+                mtime: None,
+                sha256: None,
+                kind: ShareKind::Bundle,
+                members: files,
+                session,
+                counted: true,
+                reserved: 0,
+            });
         }
+        let dir = files.pop().ok_or(ServiceError::NotFound)?;
+        Ok(DownloadTarget::single(dir, session, true, 0))
+    }
 
-        let res = self.con.execute(
-            "UPDATE share SET dl_count = ?1 WHERE slug = ?2",
-            params![dl_count + 1, slug],
+    /// Like `get_download_target`, for one file of a bundle or directory
+    /// share, see `get_member_file`. A download is one session over the
+    /// whole share: with the cookie of a live session any file the session
+    /// wasn't sent yet rides it, from byte 0 or not.
+    ///
+    /// # Errors
+    ///
+    /// Same as `get_download_target`, `NotFound` also if the share is a
+    /// plain file or has no file at `path`
+    pub fn get_member_target<'a>(
+        &self,
+        slug: &str,
+        path: &str,
+        credential: impl Into<Credential<'a>>,
+        request: DownloadRequest<'_>,
+    ) -> Result<DownloadTarget, ServiceError> {
+        let credential = credential.into();
+        let access = self.authorized_download(slug, credential, request.session)?;
+
+        let file = self
+            .get_member_file(slug, path)?
+            .ok_or(ServiceError::NotFound)?;
+        self.start_download(slug, access, credential, file, request.offset)
+    }
+
+    /// # Errors
+    ///
+    /// `NotFound` if the slug doesn't exist, `BadPassword` on failed auth
+    fn authorized_share(&self, slug: &str, credential: Credential) -> Result<Share, ServiceError> {
+        self.authorize(slug, credential).map(|(share, _)| share)
+    }
+
+    /// `authorized_share`, also returning until when (unix seconds) the
+    /// credential holds. `None` if it doesn't expire or wasn't needed.
+    ///
+    /// # Errors
+    ///
+    /// Same as `authorized_share`
+    fn authorize(
+        &self,
+        slug: &str,
+        credential: Credential,
+    ) -> Result<(Share, Option<i64>), ServiceError> {
+        let share = self.get_share(slug)?.ok_or(ServiceError::NotFound)?;
+        if share.password_hash.is_none() {
+            return Ok((share, None));
+        }
+        let until = match credential {
+            Credential::Password(password) if Self::check_password(&share, password) => None,
+            Credential::Token(token) => Some(
+                self.unlock_token_expiry(slug, token)?
+                    .ok_or(ServiceError::BadPassword)?,
+            ),
+            Credential::SignedUrl { expires } => Some(expires),
+            Credential::None | Credential::Password(_) => return Err(ServiceError::BadPassword),
+        };
+        Ok((share, until))
+    }
+
+    /// Lets a download request in by the live download session `session`
+    /// if given, otherwise by its credential, see `get_download_target`
+    ///
+    /// # Errors
+    ///
+    /// Same as `authorized_share`
+    fn authorized_download(
+        &self,
+        slug: &str,
+        credential: Credential,
+        session: Option<&str>,
+    ) -> Result<DownloadAccess, ServiceError> {
+        if let Some(session) = session {
+            if self.has_download_session(slug, session)? {
+                return Ok(DownloadAccess::Session(session.to_string()));
+            }
+        }
+        let (_, until) = self.authorize(slug, credential)?;
+        Ok(DownloadAccess::Credential { until })
+    }
+
+    /// Until when the credential behind `access` holds, checking it now if
+    /// the request came in by its download session
+    ///
+    /// # Errors
+    ///
+    /// Same as `authorized_share`
+    fn credential_until(
+        &self,
+        slug: &str,
+        access: DownloadAccess,
+        credential: Credential,
+    ) -> Result<Option<i64>, ServiceError> {
+        match access {
+            DownloadAccess::Credential { until } => Ok(until),
+            DownloadAccess::Session(_) => self.authorize(slug, credential).map(|(_, until)| until),
+        }
+    }
+
+    /// Checks the password of `slug` and hands out a token to use in its
+    /// place for `UNLOCK_TOKEN_MINUTES`, also sweeping expired tokens
+    ///
+    /// # Errors
+    ///
+    /// `NotFound` if the slug doesn't exist, `BadPassword` on failed auth
+    pub fn unlock_share(&self, slug: &str, password: &str) -> Result<ShareUnlock, ServiceError> {
+        self.authorized_share(slug, Credential::Password(password))?;
+
+        let con = self.con()?;
+        con.execute(
+            "DELETE FROM share_unlock WHERE expires_at <= datetime('now')",
+            [],
+        )?;
+        let token = gen_session_token();
+        let expires_at = con.query_one(
+            "INSERT INTO share_unlock (token, slug, expires_at)
+            VALUES (?1, ?2, datetime('now', ?3))
+            RETURNING expires_at",
+            params![token, slug, format!("+{UNLOCK_TOKEN_MINUTES} minutes")],
+            |r| r.get(0),
         )?;
+        Ok(ShareUnlock { token, expires_at })
+    }
 
-        Ok(res > 0)
+    /// Expiry (unix seconds) of `token` if it's a live unlock token for `slug`
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    fn unlock_token_expiry(&self, slug: &str, token: &str) -> Result<Option<i64>, ServiceError> {
+        let con = self.con()?;
+        Ok(con
+            .query_row(
+                "SELECT CAST(strftime('%s', expires_at) AS INTEGER) FROM share_unlock
+                WHERE token = ?1 AND slug = ?2 AND expires_at > datetime('now')",
+                params![token, slug],
+                |r| r.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Serves `file` from byte `offset` in the session `authorized_download`
+    /// found while that session has bytes of it left, otherwise counts a new
+    /// download with its own session
+    ///
+    /// # Errors
+    ///
+    /// `BadPassword` if the session is used up and the credential fails,
+    /// `Expired` / `LimitReached` if the share is used up
+    fn start_download(
+        &self,
+        slug: &str,
+        access: DownloadAccess,
+        credential: Credential,
+        file: FileEntry,
+        offset: u64,
+    ) -> Result<DownloadTarget, ServiceError> {
+        // At most the rest of the file, all of it for suffix ranges
+        let reserved = match i64::try_from(offset) {
+            Ok(offset) if offset < file.size_bytes => file.size_bytes - offset,
+            _ => file.size_bytes,
+        };
+        if let DownloadAccess::Session(session) = &access {
+            if self.is_expired(slug)? {
+                return Err(ServiceError::Expired);
+            }
+            if self.reserve_download_bytes(session, &file, reserved)? {
+                let session = session.clone();
+                return Ok(DownloadTarget::single(file, session, false, reserved));
+            }
+        }
+
+        let until = self.credential_until(slug, access, credential)?;
+        let session = self.count_download(slug, until)?;
+        self.reserve_download_bytes(&session, &file, reserved)?;
+        Ok(DownloadTarget::single(file, session, true, reserved))
     }
 
+    /// Counts a new download of `slug` and starts its session
+    ///
     /// # Errors
     ///
-    /// basic fail cases
-    /// runs `check_password` and `increased_dl`
-    /// if return error is `UnwindingPanic`: failed auth
-    pub fn get_download_target(
+    /// `Expired` / `LimitReached` if the share is used up
+    fn count_download(&self, slug: &str, until: Option<i64>) -> Result<String, ServiceError> {
+        if !self.increase_dl(slug)? {
+            return Err(self.gone_reason(slug)?);
+        }
+        self.create_download_session(slug, until)
+    }
+
+    /// Adds `bytes` of `file` to what `session` was sent, unless it was sent
+    /// the whole file already. Check and add are one statement, so parallel
+    /// requests can't all slip in under the file's size.
+    /// False if the session has no bytes of the file left.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    fn reserve_download_bytes(
+        &self,
+        session: &str,
+        file: &FileEntry,
+        bytes: i64,
+    ) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        // Empty files can't be used up
+        let changed = con.execute(
+            "INSERT INTO download_progress (session, path, bytes) VALUES (?1, ?2, ?3)
+            ON CONFLICT (session, path) DO UPDATE SET bytes = bytes + excluded.bytes
+            WHERE bytes < MAX(?4, 1)",
+            params![session, file.abs_path, bytes, file.size_bytes],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Hands back the `bytes` of a `DownloadTarget::reserved` reservation
+    /// that weren't sent, so the session can resume them
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn return_download_bytes(
+        &self,
+        session: &str,
+        abs_path: &str,
+        bytes: i64,
+    ) -> Result<(), ServiceError> {
+        self.con()?.execute(
+            "UPDATE download_progress SET bytes = MAX(bytes - ?3, 0)
+            WHERE session = ?1 AND path = ?2",
+            params![session, abs_path, bytes],
+        )?;
+        Ok(())
+    }
+
+    /// Compares a registered file with what's on disk now. On size / mtime
+    /// drift the `FileChangePolicy` decides: `Refresh` records the new values
+    /// and returns the updated entry, `Refuse` errors.
+    ///
+    /// # Errors
+    ///
+    /// `FileMissing` if the file is gone,
+    /// `FileChanged` if it drifted under `Refuse`,
+    /// `Io` if its metadata can't be read
+    pub fn check_file_on_disk(&self, file: &FileEntry) -> Result<FileEntry, ServiceError> {
+        let metadata = std::fs::metadata(&file.abs_path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ServiceError::FileMissing,
+            _ => ServiceError::Io(e),
+        })?;
+        // Directories are rescanned when archived, not on every check
+        if file.kind == FileKind::Dir {
+            return if metadata.is_dir() {
+                Ok(file.clone())
+            } else {
+                Err(ServiceError::FileMissing)
+            };
+        }
+        if !metadata.is_file() {
+            return Err(ServiceError::FileMissing);
+        }
+
+        let size_bytes = i64::try_from(metadata.len())
+            .map_err(|_| ServiceError::Invalid("file too large".into()))?;
+        let mtime = unix_mtime(&metadata);
+        if size_bytes == file.size_bytes && mtime == file.mtime {
+            return Ok(file.clone());
+        }
+
+        match self.on_change {
+            FileChangePolicy::Refuse => {
+                log::warn!("refusing {}: changed on disk", file.abs_path);
+                Err(ServiceError::FileChanged)
+            }
+            FileChangePolicy::Refresh => {
+                log::info!("refreshing metadata of {}", file.abs_path);
+                // The old digest describes the old content
+                self.con()?.execute(
+                    "UPDATE file SET size_bytes = ?1, mtime = ?2,
+                        sha256 = NULL, hashed_at = NULL, hash_state = NULL
+                    WHERE id = ?3",
+                    params![size_bytes, mtime, file.id],
+                )?;
+                let mut refreshed = FileEntry {
+                    size_bytes,
+                    mtime,
+                    sha256: None,
+                    hash_state: None,
+                    ..file.clone()
+                };
+                self.hash_or_schedule(&mut refreshed);
+                Ok(refreshed)
+            }
+        }
+    }
+
+    /// File a share points to, without any access checks or counting
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn get_share_file(&self, slug: &str) -> Result<Option<FileEntry>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            &format!(
+                "SELECT {FILE_COLUMNS}
+                FROM share s
+                JOIN file f ON s.file_id = f.id
+                WHERE s.slug = ?1"
+            ),
+            params![slug],
+            file_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// Files of a share in order; one for plain shares, several for bundles
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn get_bundle_files(&self, slug: &str) -> Result<Vec<FileEntry>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(&format!(
+            "SELECT {FILE_COLUMNS}
+            FROM share_file sf
+            JOIN file f ON sf.file_id = f.id
+            WHERE sf.slug = ?1
+            ORDER BY sf.position"
+        ))?;
+        let files = stmt
+            .query_map(params![slug], file_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(files)
+    }
+
+    /// One file of a bundle by name, or of a directory share by its path
+    /// below the directory. Bundle files are checked on disk, files in a
+    /// directory are read fresh and carry the directory's id.
+    /// `None` for unknown or unreachable paths and plain file shares.
+    ///
+    /// # Errors
+    ///
+    /// Errors of `check_file_on_disk`, otherwise db failure to read
+    pub fn get_member_file(
         &self,
         slug: &str,
-        password: &str,
-    ) -> Result<Option<(String, String)>, rusqlite::Error> {
-        let authenticated = self.check_password(slug, password)?;
-        if !authenticated {
-            return Err(rusqlite::Error::UnwindingPanic);
+        path: &str,
+    ) -> Result<Option<FileEntry>, ServiceError> {
+        let files = self.get_bundle_files(slug)?;
+        match files.as_slice() {
+            [dir] if dir.kind == FileKind::Dir => {
+                let dir = self.check_file_on_disk(dir)?;
+                let entry = archive::resolve(Path::new(&dir.abs_path), path, &self.policy)
+                    .filter(|e| !e.is_dir);
+                Ok(entry.map(|entry| FileEntry {
+                    id: dir.id,
+                    abs_path: entry.source.to_string_lossy().into_owned(),
+                    name: entry
+                        .name
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    size_bytes: i64::try_from(entry.size).unwrap_or(i64::MAX),
+                    mtime: entry.mtime,
+                    sha256: None,
+                    hash_state: None,
+                    created_at: dir.created_at,
+                    kind: FileKind::File,
+                    entry_count: None,
+                }))
+            }
+            [_] | [] => Ok(None),
+            _ => files
+                .iter()
+                .find(|f| f.name == path)
+                .map(|f| self.check_file_on_disk(f))
+                .transpose(),
         }
+    }
 
-        let abs_path = self
-            .con
-            .query_one(
-                "SELECT f.abs_path, f.name
-            FROM share s
-            JOIN file f ON s.file_id = f.id
-            WHERE s.slug = ?1",
-                params![slug],
-                |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)),
+    /// Size and mtime on disk of the file a download of `slug`, or of its
+    /// file at `path`, would serve. Only reads: no access checks, nothing
+    /// recorded and no rehash, so it's safe before the request is let in.
+    /// `None` if there's no such file.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn disk_validators(
+        &self,
+        slug: &str,
+        path: Option<&str>,
+    ) -> Result<Option<(i64, Option<i64>)>, ServiceError> {
+        let files = self.get_bundle_files(slug)?;
+        let source = match (files.as_slice(), path) {
+            ([file], None) if file.kind == FileKind::File => Some(PathBuf::from(&file.abs_path)),
+            ([dir], Some(path)) if dir.kind == FileKind::Dir => {
+                archive::resolve(Path::new(&dir.abs_path), path, &self.policy)
+                    .filter(|e| !e.is_dir)
+                    .map(|e| e.source)
+            }
+            ([_, _, ..], Some(path)) => files
+                .iter()
+                .find(|f| f.name == path)
+                .map(|f| PathBuf::from(&f.abs_path)),
+            _ => None,
+        };
+        let metadata = source.and_then(|p| std::fs::metadata(p).ok());
+        Ok(metadata
+            .filter(std::fs::Metadata::is_file)
+            .map(|m| (i64::try_from(m.len()).unwrap_or(i64::MAX), unix_mtime(&m))))
+    }
+
+    /// Lists the directory `path` of a directory share
+    ///
+    /// # Errors
+    ///
+    /// `NotFound` for unknown slugs, shares that aren't directories and
+    /// paths `archive::resolve` refuses, `BadPassword` on failed auth,
+    /// `Expired` once the share expired
+    pub fn list_share_dir<'a>(
+        &self,
+        slug: &str,
+        path: &str,
+        credential: impl Into<Credential<'a>>,
+    ) -> Result<DirListing, ServiceError> {
+        self.authorized_share(slug, credential.into())?;
+        if self.is_expired(slug)? {
+            return Err(ServiceError::Expired);
+        }
+        let dir = self
+            .get_share_file(slug)?
+            .filter(|f| f.kind == FileKind::Dir)
+            .ok_or(ServiceError::NotFound)?;
+        let dir = self.check_file_on_disk(&dir)?;
+
+        let path = path.trim_matches('/');
+        let entries = archive::list_dir(Path::new(&dir.abs_path), path, &self.policy)
+            .ok_or(ServiceError::NotFound)?;
+        Ok(DirListing {
+            path: path.to_string(),
+            entries: entries.into_iter().map(TreeEntry::from).collect(),
+        })
+    }
+
+    // ————— upload sessions —————
+
+    /// Starts a chunked upload of `size_bytes`, also sweeping stale ones.
+    /// The caller creates the partial file and removes the swept ones.
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for a negative size, otherwise db failure to write
+    pub fn create_upload_session(
+        &self,
+        file_name: &str,
+        size_bytes: i64,
+    ) -> Result<UploadSession, ServiceError> {
+        if size_bytes < 0 {
+            return Err(ServiceError::Invalid("size_bytes must be >= 0".into()));
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let con = self.con()?;
+        con.query_row(
+            &format!(
+                "INSERT INTO upload_session (id, file_name, size_bytes, expires_at)
+                VALUES (?1, ?2, ?3, datetime('now', ?4))
+                RETURNING {UPLOAD_SESSION_COLUMNS}"
+            ),
+            params![
+                id,
+                file_name,
+                size_bytes,
+                format!("+{UPLOAD_SESSION_HOURS} hours")
+            ],
+            upload_session_from_row,
+        )
+        .map_err(ServiceError::from)
+    }
+
+    /// Live (not expired) upload session
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn get_upload_session(&self, id: &str) -> Result<Option<UploadSession>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            &format!(
+                "SELECT {UPLOAD_SESSION_COLUMNS} FROM upload_session
+                WHERE id = ?1 AND expires_at > datetime('now')"
+            ),
+            params![id],
+            upload_session_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// Records how far the upload got and pushes its expiry out again,
+    /// an upload that keeps making progress never goes stale
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn set_upload_received(
+        &self,
+        id: &str,
+        received_bytes: i64,
+    ) -> Result<Option<UploadSession>, ServiceError> {
+        let con = self.con()?;
+        con.query_row(
+            &format!(
+                "UPDATE upload_session
+                SET received_bytes = ?1, expires_at = datetime('now', ?2)
+                WHERE id = ?3
+                RETURNING {UPLOAD_SESSION_COLUMNS}"
+            ),
+            params![received_bytes, format!("+{UPLOAD_SESSION_HOURS} hours"), id],
+            upload_session_from_row,
+        )
+        .optional()
+        .map_err(ServiceError::from)
+    }
+
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn delete_upload_session(&self, id: &str) -> Result<bool, ServiceError> {
+        let changed = self
+            .con()?
+            .execute("DELETE FROM upload_session WHERE id = ?1", params![id])?;
+        Ok(changed > 0)
+    }
+
+    /// Drops expired upload sessions, returns their ids so the partial
+    /// files can be removed
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn purge_stale_upload_sessions(&self) -> Result<Vec<String>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(
+            "DELETE FROM upload_session WHERE expires_at <= datetime('now') RETURNING id",
+        )?;
+        let ids = stmt
+            .query_map([], |r| r.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(ids)
+    }
+
+    /// Ids of all live upload sessions
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn upload_session_ids(&self) -> Result<Vec<String>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt =
+            con.prepare("SELECT id FROM upload_session WHERE expires_at > datetime('now')")?;
+        let ids = stmt
+            .query_map([], |r| r.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(ids)
+    }
+
+    // ————— password lockouts —————
+
+    /// Runs `f`, which checks the password of `slug`, under brute-force
+    /// protection: refused up front while the slug or `client` is locked
+    /// out, and a `BadPassword` from `f` counts against both. Shares
+    /// without a password aren't affected.
+    ///
+    /// The attempt is counted as a failure before `f` runs and taken back
+    /// after, so parallel guesses can't all slip in under the limit. The
+    /// right password only takes back its own attempt, failures others
+    /// piled up on the slug stay until they go quiet or an admin clears them.
+    ///
+    /// # Errors
+    ///
+    /// `TooManyAttempts` during a lockout, otherwise whatever `f` returns
+    pub fn guard_password<T>(
+        &self,
+        slug: &str,
+        client: Option<&str>,
+        f: impl FnOnce(&Self) -> Result<T, ServiceError>,
+    ) -> Result<T, ServiceError> {
+        let protected = self
+            .get_share(slug)?
+            .is_some_and(|s| s.password_hash.is_some());
+        if !protected {
+            return f(self);
+        }
+
+        let attempts = self.reserve_password_attempt(slug, client)?;
+        let res = f(self);
+        if !matches!(res, Err(ServiceError::BadPassword)) {
+            self.take_back_attempts(attempts.iter())?;
+        }
+        res
+    }
+
+    /// Counts an attempt as failed against `slug` and `client`, locking
+    /// them out once `LockoutPolicy` says so. Check and count are one
+    /// transaction. Also sweeps counters that went quiet.
+    ///
+    /// # Errors
+    ///
+    /// `TooManyAttempts` if either is locked out already,
+    /// otherwise generic db failure
+    fn reserve_password_attempt(
+        &self,
+        slug: &str,
+        client: Option<&str>,
+    ) -> Result<Vec<PasswordAttempt>, ServiceError> {
+        let mut con = self.con()?;
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM password_failure
+            WHERE last_failed_at <= datetime('now', '-1 day')
+              AND (locked_until IS NULL OR locked_until <= datetime('now'))",
+            [],
+        )?;
+        let remaining: Option<i64> = tx.query_one(
+            "SELECT MAX(strftime('%s', locked_until) - strftime('%s', 'now'))
+            FROM password_failure
+            WHERE locked_until > datetime('now')
+              AND ((scope = 'slug' AND key = ?1) OR (scope = 'ip' AND key = ?2))",
+            params![slug, client],
+            |r| r.get(0),
+        )?;
+        if let Some(secs) = remaining {
+            return Err(ServiceError::TooManyAttempts {
+                retry_after: u64::try_from(secs).unwrap_or(0).max(1),
+            });
+        }
+
+        let mut attempts = Vec::new();
+        let keys = [(LockoutScope::Slug, Some(slug)), (LockoutScope::Ip, client)];
+        for (scope, key) in keys {
+            let Some(key) = key else { continue };
+            let prev_locked_until: Option<String> = tx
+                .query_row(
+                    "SELECT locked_until FROM password_failure WHERE scope = ?1 AND key = ?2",
+                    params![scope, key],
+                    |r| r.get(0),
+                )
+                .optional()?
+                .flatten();
+            let failures: i64 = tx.query_one(
+                "INSERT INTO password_failure (scope, key, failures) VALUES (?1, ?2, 1)
+                ON CONFLICT (scope, key) DO UPDATE SET
+                    failures = failures + 1,
+                    last_failed_at = datetime('now')
+                RETURNING failures",
+                params![scope, key],
+                |r| r.get(0),
+            )?;
+
+            let mut locked_until = prev_locked_until.clone();
+            if let Some(secs) = self
+                .lockout
+                .lockout_secs(u32::try_from(failures).unwrap_or(u32::MAX))
+            {
+                log::warn!(
+                    "locking out {scope:?} {key} for {secs}s after {failures} failed passwords"
+                );
+                locked_until = Some(tx.query_one(
+                    "UPDATE password_failure SET locked_until = datetime('now', ?3)
+                    WHERE scope = ?1 AND key = ?2
+                    RETURNING locked_until",
+                    params![scope, key, format!("+{secs} seconds")],
+                    |r| r.get(0),
+                )?);
+            }
+            attempts.push(PasswordAttempt {
+                scope,
+                key: key.to_string(),
+                prev_locked_until,
+                locked_until,
+            });
+        }
+        tx.commit()?;
+        Ok(attempts)
+    }
+
+    /// Undoes attempts that turned out not to be wrong passwords, lifting
+    /// a lockout they started unless another attempt moved it since
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    fn take_back_attempts<'a>(
+        &self,
+        attempts: impl Iterator<Item = &'a PasswordAttempt>,
+    ) -> Result<(), ServiceError> {
+        let mut con = self.con()?;
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for attempt in attempts {
+            tx.execute(
+                "UPDATE password_failure SET
+                    failures = failures - 1,
+                    locked_until = CASE WHEN locked_until IS ?3 THEN ?4 ELSE locked_until END
+                WHERE scope = ?1 AND key = ?2",
+                params![
+                    attempt.scope,
+                    attempt.key,
+                    attempt.locked_until,
+                    attempt.prev_locked_until
+                ],
+            )?;
+            tx.execute(
+                "DELETE FROM password_failure WHERE scope = ?1 AND key = ?2 AND failures <= 0",
+                params![attempt.scope, attempt.key],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Failure counters, locked out ones first
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn list_password_failures(&self) -> Result<Vec<PasswordFailures>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(
+            "SELECT scope, key, failures, locked_until, last_failed_at
+            FROM password_failure
+            ORDER BY locked_until > datetime('now') DESC, last_failed_at DESC",
+        )?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok(PasswordFailures {
+                    scope: r.get(0)?,
+                    key: r.get(1)?,
+                    failures: r.get(2)?,
+                    locked_until: r.get(3)?,
+                    last_failed_at: r.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Forgets the failures of a slug or IP, lifting any lockout
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn clear_password_failures(
+        &self,
+        scope: LockoutScope,
+        key: &str,
+    ) -> Result<bool, ServiceError> {
+        let changed = self.con()?.execute(
+            "DELETE FROM password_failure WHERE scope = ?1 AND key = ?2",
+            params![scope, key],
+        )?;
+        Ok(changed > 0)
+    }
+
+    // ————— signing keys —————
+
+    /// All keys for signed URLs, oldest first
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn signing_keys(&self) -> Result<Vec<SigningKey>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare("SELECT id, secret, created_at FROM signing_key ORDER BY id")?;
+        let keys = stmt
+            .query_map([], |r| {
+                Ok(SigningKey {
+                    id: r.get(0)?,
+                    secret: r.get(1)?,
+                    created_at: r.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(keys)
+    }
+
+    /// Adds a fresh key, which signs from now on. Older keys keep verifying
+    /// until deleted.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn rotate_signing_key(&self) -> Result<SigningKey, ServiceError> {
+        use rand_core::RngCore;
+        let mut secret = vec![0u8; SESSION_TOKEN_BYTES];
+        OsRng.fill_bytes(&mut secret);
+
+        let con = self.con()?;
+        let (id, created_at) = con.query_one(
+            "INSERT INTO signing_key (secret) VALUES (?1) RETURNING id, created_at",
+            params![secret],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )?;
+        Ok(SigningKey {
+            id,
+            secret,
+            created_at,
+        })
+    }
+
+    /// Deletes a key, invalidating every URL signed with it. The newest key
+    /// can't be deleted, rotate first.
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for the newest key, otherwise db failure to write
+    pub fn delete_signing_key(&self, id: i64) -> Result<bool, ServiceError> {
+        let con = self.con()?;
+        let newest: Option<i64> =
+            con.query_one("SELECT MAX(id) FROM signing_key", [], |r| r.get(0))?;
+        if newest == Some(id) {
+            return Err(ServiceError::Invalid(
+                "the newest signing key is in use, rotate before deleting it".into(),
+            ));
+        }
+        let changed = con.execute("DELETE FROM signing_key WHERE id = ?1", params![id])?;
+        Ok(changed > 0)
+    }
+
+    // ————— janitor —————
+
+    /// Removes shares past `expires_at` or out of downloads, keeping a
+    /// record of them per `policy`. Shares with a live download session
+    /// are left for the next run, so a resumed download can finish.
+    /// Returns the slugs removed.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn purge_dead_shares(
+        &self,
+        policy: ExpiredSharePolicy,
+    ) -> Result<Vec<String>, ServiceError> {
+        const DEAD: &str = "(
+                (s.expires_at IS NOT NULL AND julianday(s.expires_at) <= julianday('now'))
+                OR (s.max_downloads IS NOT NULL AND s.dl_count >= s.max_downloads)
             )
-            .optional();
+            AND NOT EXISTS (
+                SELECT 1 FROM download_session d
+                WHERE d.slug = s.slug AND d.expires_at > datetime('now'))";
+
+        let mut con = self.con()?;
+        let tx = con.transaction()?;
+        if policy == ExpiredSharePolicy::Archive {
+            tx.execute(
+                &format!(
+                    "INSERT INTO share_archive (slug, abs_path, file_count, reason,
+                        expires_at, max_downloads, dl_count, created_at)
+                    SELECT s.slug, f.abs_path,
+                        (SELECT COUNT(*) FROM share_file sf WHERE sf.slug = s.slug),
+                        CASE WHEN s.expires_at IS NOT NULL
+                            AND julianday(s.expires_at) <= julianday('now')
+                            THEN 'expired' ELSE 'exhausted' END,
+                        s.expires_at, s.max_downloads, s.dl_count, s.created_at
+                    FROM share s JOIN file f ON f.id = s.file_id
+                    WHERE {DEAD}"
+                ),
+                [],
+            )?;
+        }
+        let slugs = tx
+            .prepare(&format!(
+                "DELETE FROM share AS s WHERE {DEAD} RETURNING slug"
+            ))?
+            .query_map([], |r| r.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        tx.commit()?;
+        Ok(slugs)
+    }
+
+    /// Removes file rows no share refers to, registered at least
+    /// `grace_secs` ago so a file can be shared after it's registered.
+    /// Returns the removed rows' paths.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn purge_orphan_files(&self, grace_secs: u64) -> Result<Vec<String>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(
+            "DELETE FROM file
+            WHERE id NOT IN (SELECT file_id FROM share)
+              AND id NOT IN (SELECT file_id FROM share_file)
+              AND created_at <= datetime('now', ?1)
+            RETURNING abs_path",
+        )?;
+        let paths = stmt
+            .query_map(params![format!("-{grace_secs} seconds")], |r| r.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths)
+    }
+
+    /// Drops expired admin, download and unlock sessions and password
+    /// failure counters that went quiet, returns how many rows went
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn purge_expired_sessions(&self) -> Result<usize, ServiceError> {
+        let con = self.con()?;
+        let mut removed = 0;
+        for sql in [
+            "DELETE FROM admin_session WHERE expires_at <= datetime('now')",
+            "DELETE FROM download_session WHERE expires_at <= datetime('now')",
+            "DELETE FROM share_unlock WHERE expires_at <= datetime('now')",
+            "DELETE FROM password_failure
+            WHERE last_failed_at <= datetime('now', '-1 day')
+              AND (locked_until IS NULL OR locked_until <= datetime('now'))",
+        ] {
+            removed += con.execute(sql, [])?;
+        }
+        Ok(removed)
+    }
+
+    /// Shares the janitor archived, newest first
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to read
+    pub fn list_archived_shares(&self) -> Result<Vec<ArchivedShare>, ServiceError> {
+        let con = self.con()?;
+        let mut stmt = con.prepare(
+            "SELECT id, slug, abs_path, file_count, reason, expires_at, max_downloads,
+                dl_count, created_at, archived_at
+            FROM share_archive ORDER BY id DESC",
+        )?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok(ArchivedShare {
+                    id: r.get(0)?,
+                    slug: r.get(1)?,
+                    abs_path: r.get(2)?,
+                    file_count: r.get(3)?,
+                    reason: r.get(4)?,
+                    expires_at: r.get(5)?,
+                    max_downloads: r.get(6)?,
+                    dl_count: r.get(7)?,
+                    created_at: r.get(8)?,
+                    archived_at: r.get(9)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Lets SQLite refresh its query planner statistics, cheap enough to
+    /// run often
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn optimize(&self) -> Result<(), ServiceError> {
+        self.con()?.execute_batch("PRAGMA optimize")?;
+        Ok(())
+    }
+
+    /// Rewrites the DB file to give space of deleted rows back. Blocks
+    /// writers while it runs.
+    ///
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn vacuum(&self) -> Result<(), ServiceError> {
+        self.con()?.execute_batch("VACUUM")?;
+        Ok(())
+    }
+
+    // ————— download audit log —————
 
-        let increased_dl = self.increase_dl(slug)?;
-        if !increased_dl {
-            return Err(rusqlite::Error::InvalidQuery);
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn record_download_event(&self, event: &NewDownloadEvent) -> Result<(), ServiceError> {
+        self.con()?.execute(
+            "INSERT INTO download_event (slug, file_id, ip, user_agent, bytes_sent, range, outcome)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                event.slug,
+                event.file_id,
+                event.ip,
+                event.user_agent,
+                i64::try_from(event.bytes_sent).unwrap_or(i64::MAX),
+                event.range,
+                event.outcome.unwrap_or(DownloadOutcome::Error),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Download events matching `query`, newest first
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for a `from` / `to` that isn't a date or a `limit` out of
+    /// range, otherwise db failure to read
+    pub fn list_download_events(
+        &self,
+        query: &DownloadEventQuery,
+    ) -> Result<DownloadEventPage, ServiceError> {
+        const FILTER: &str = "(?1 IS NULL OR slug = ?1)
+            AND (?2 IS NULL OR file_id = ?2)
+            AND (?3 IS NULL OR julianday(at) >= julianday(?3))
+            AND (?4 IS NULL OR julianday(at) < julianday(?4))";
+
+        let limit = self.validate_page(query.limit, &query.from, &query.to)?;
+        let offset = query.offset.unwrap_or(0);
+
+        let con = self.con()?;
+        let filters = params![query.slug, query.file_id, query.from, query.to];
+        let total = con.query_one(
+            &format!("SELECT COUNT(*) FROM download_event WHERE {FILTER}"),
+            filters,
+            |r| r.get(0),
+        )?;
+        let mut stmt = con.prepare(&format!(
+            "SELECT id, slug, file_id, at, ip, user_agent, bytes_sent, range, outcome
+            FROM download_event WHERE {FILTER}
+            ORDER BY id DESC LIMIT ?5 OFFSET ?6"
+        ))?;
+        let events = stmt
+            .query_map(
+                params![
+                    query.slug,
+                    query.file_id,
+                    query.from,
+                    query.to,
+                    limit,
+                    offset
+                ],
+                |r| {
+                    Ok(DownloadEvent {
+                        id: r.get(0)?,
+                        slug: r.get(1)?,
+                        file_id: r.get(2)?,
+                        at: r.get(3)?,
+                        ip: r.get(4)?,
+                        user_agent: r.get(5)?,
+                        bytes_sent: r.get(6)?,
+                        range: r.get(7)?,
+                        outcome: r.get(8)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DownloadEventPage {
+            events,
+            total,
+            limit,
+            offset,
+        })
+    }
+
+    /// Checks the paging and time range of an audit log query, returns
+    /// the page size
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for a `from` / `to` that isn't a date or a `limit` out of
+    /// range
+    fn validate_page(
+        &self,
+        limit: Option<u32>,
+        from: &Option<String>,
+        to: &Option<String>,
+    ) -> Result<u32, ServiceError> {
+        let limit = limit.unwrap_or(DOWNLOAD_EVENTS_PAGE);
+        if !(1..=MAX_DOWNLOAD_EVENTS_PAGE).contains(&limit) {
+            return Err(ServiceError::Invalid(format!(
+                "limit must be between 1 and {MAX_DOWNLOAD_EVENTS_PAGE}"
+            )));
+        }
+        for (name, date) in [("from", from), ("to", to)] {
+            if let Some(date) = date {
+                self.validate_date(name, date)?;
+            }
         }
+        Ok(limit)
+    }
+
+    // ————— admin audit trail —————
+
+    /// # Errors
+    ///
+    /// Fails only with generic db failure to write
+    pub fn record_admin_audit(&self, entry: &NewAdminAudit) -> Result<(), ServiceError> {
+        self.con()?.execute(
+            "INSERT INTO admin_audit (actor, action, target, params, ip, status)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.actor,
+                entry.action,
+                entry.target,
+                entry.params.as_ref().map(ToString::to_string),
+                entry.ip,
+                entry.status,
+            ],
+        )?;
+        Ok(())
+    }
 
-        abs_path
+    /// Admin audit entries matching `query`, newest first
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for a `from` / `to` that isn't a date or a `limit` out of
+    /// range, otherwise db failure to read
+    pub fn list_admin_audit(
+        &self,
+        query: &AdminAuditQuery,
+    ) -> Result<AdminAuditPage, ServiceError> {
+        const FILTER: &str = "(?1 IS NULL OR actor = ?1)
+            AND (?2 IS NULL OR action = ?2)
+            AND (?3 IS NULL OR target = ?3)
+            AND (?4 IS NULL OR instr(target, ?4) > 0 OR instr(params, ?4) > 0)
+            AND (?5 IS NULL OR julianday(at) >= julianday(?5))
+            AND (?6 IS NULL OR julianday(at) < julianday(?6))";
+
+        let limit = self.validate_page(query.limit, &query.from, &query.to)?;
+        let offset = query.offset.unwrap_or(0);
+        let filters = [
+            &query.actor,
+            &query.action,
+            &query.target,
+            &query.q,
+            &query.from,
+            &query.to,
+        ];
+
+        let con = self.con()?;
+        let total = con.query_one(
+            &format!("SELECT COUNT(*) FROM admin_audit WHERE {FILTER}"),
+            filters,
+            |r| r.get(0),
+        )?;
+        let mut stmt = con.prepare(&format!(
+            "SELECT id, at, actor, action, target, params, ip, status
+            FROM admin_audit WHERE {FILTER}
+            ORDER BY id DESC LIMIT ?7 OFFSET ?8"
+        ))?;
+        let [actor, action, target, q, from, to] = filters;
+        let entries = stmt
+            .query_map(
+                params![actor, action, target, q, from, to, limit, offset],
+                |r| {
+                    let params: Option<String> = r.get(5)?;
+                    Ok(AdminAuditEntry {
+                        id: r.get(0)?,
+                        at: r.get(1)?,
+                        actor: r.get(2)?,
+                        action: r.get(3)?,
+                        target: r.get(4)?,
+                        // Written by `record_admin_audit`, always JSON
+                        params: params.and_then(|p| serde_json::from_str(&p).ok()),
+                        ip: r.get(6)?,
+                        status: r.get(7)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(AdminAuditPage {
+            entries,
+            total,
+            limit,
+            offset,
+        })
     }
 }
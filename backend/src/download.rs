@@ -0,0 +1,476 @@
+// src/download.rs
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{
+        EntityTag, Header, HeaderMap, HeaderName, IfRange, IF_RANGE, RANGE, USER_AGENT,
+        X_FORWARDED_FOR,
+    },
+    middleware::Next,
+    web::{self, Bytes},
+    HttpMessage, HttpRequest,
+};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::db::{Db, DownloadEvent, DownloadOutcome, DownloadTarget, NewDownloadEvent};
+use crate::error::ServiceError;
+
+/// Cookie holding the download session id, scoped to one share's download path
+pub const DOWNLOAD_SESSION_COOKIE: &str = "fs_dl";
+/// Cookie holding a share's unlock token, scoped to its download and share paths
+pub const UNLOCK_COOKIE: &str = "fs_unlock";
+
+/// Strong ETag from the size and mtime recorded at registration.
+/// `None` for files registered before mtime was recorded.
+#[must_use]
+pub fn entity_tag(size_bytes: i64, mtime: Option<i64>) -> Option<EntityTag> {
+    mtime.map(|m| EntityTag::new_strong(format!("{size_bytes:x}-{m:x}")))
+}
+
+/// RFC 9110 If-Range: the range only applies if the validator still matches,
+/// otherwise the whole file must be sent. No If-Range header always matches.
+#[must_use]
+pub fn if_range_matches(if_range: Option<&IfRange>, size_bytes: i64, mtime: Option<i64>) -> bool {
+    match if_range {
+        None => true,
+        Some(IfRange::EntityTag(tag)) => {
+            entity_tag(size_bytes, mtime).is_some_and(|ours| tag.strong_eq(&ours))
+        }
+        Some(IfRange::Date(date)) => mtime
+            .and_then(|m| u64::try_from(m).ok())
+            .is_some_and(|m| SystemTime::from(*date) == UNIX_EPOCH + Duration::from_secs(m)),
+    }
+}
+
+/// Middleware for the download routes. `NamedFile` ignores If-Range, so when
+/// the client's validator doesn't match the file on disk this drops the
+/// Range header and the client gets the full file instead of a stale piece.
+/// It runs before the request is let in, so it only compares, see
+/// `Db::disk_validators`.
+///
+/// # Errors
+///
+/// Only passes on errors from the wrapped handler or the DB lookup
+pub async fn strip_stale_range(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if req.headers().contains_key(RANGE) && req.headers().contains_key(IF_RANGE) {
+        let if_range = IfRange::parse(&req).ok();
+        let slug = req.match_info().get("slug").map(str::to_string);
+        // Set on the per-file route of bundles and directory shares
+        let path = req.match_info().get("path").map(str::to_string);
+        let db = req.app_data::<web::Data<Db>>().cloned();
+
+        if let (Some(slug), Some(db)) = (slug, db) {
+            let validators = db
+                .blocking(move |db| db.disk_validators(&slug, path.as_deref()))
+                .await?;
+            if let Some((size_bytes, mtime)) = validators {
+                if !if_range_matches(if_range.as_ref(), size_bytes, mtime) {
+                    req.headers_mut().remove(RANGE);
+                }
+            }
+        }
+    }
+
+    next.call(req).await
+}
+
+/// Reverse proxies whose `X-Forwarded-For` is believed, as addresses or
+/// CIDR ranges. Empty trusts no one, the peer address is the client.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    nets: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// # Errors
+    ///
+    /// `InvalidInput` naming the first entry that isn't an address or range
+    pub fn new(entries: &[String]) -> io::Result<Self> {
+        let nets = entries
+            .iter()
+            .map(|entry| {
+                parse_net(entry).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("not an IP address or CIDR range: {entry}"),
+                    )
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { nets })
+    }
+
+    #[must_use]
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|&(net, prefix)| match (net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// The client behind `peer`: walks `X-Forwarded-For` from the right
+    /// while the hops are trusted proxies, the first other address is the
+    /// client. Anyone can write the header, so without a trusted peer it
+    /// means nothing.
+    #[must_use]
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.trusts(peer) {
+            return client;
+        }
+        let hops: Vec<&str> = headers
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            let Some(ip) = parse_hop(hop.trim()) else {
+                break;
+            };
+            client = ip;
+            if !self.trusts(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+fn parse_net(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry, None),
+    };
+    let addr: IpAddr = addr.trim().parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.trim().parse().ok().filter(|p| *p <= max)?,
+        None => max,
+    };
+    Some((addr.to_canonical(), prefix))
+}
+
+/// Forwarded addresses may carry a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|a| a.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// Address of the client, the key of per-IP lockouts and limits. Behind
+/// a proxy listed in the app's `TrustedProxies` it's taken from
+/// `X-Forwarded-For`.
+#[must_use]
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let ip = match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_ip(peer, req.headers()),
+        None => peer.to_canonical(),
+    };
+    Some(ip.to_string())
+}
+
+/// Unlock token of the request: the `token` query parameter if given,
+/// otherwise the unlock cookie
+#[must_use]
+pub fn unlock_token(req: &HttpRequest, query: Option<String>) -> Option<String> {
+    query.or_else(|| req.cookie(UNLOCK_COOKIE).map(|c| c.value().to_string()))
+}
+
+/// Session id sent back by the client, if any
+#[must_use]
+pub fn download_session(req: &HttpRequest) -> Option<String> {
+    req.cookie(DOWNLOAD_SESSION_COOKIE)
+        .map(|c| c.value().to_string())
+}
+
+/// First byte the request's Range header asks for, 0 without a usable one.
+/// Suffix ranges (`bytes=-N`) count as past byte 0: they ask for the end.
+#[must_use]
+pub fn range_start(req: &HttpRequest) -> u64 {
+    let Some(ranges) = req
+        .headers()
+        .get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes="))
+    else {
+        return 0;
+    };
+    ranges
+        .split(',')
+        .map(|spec| match spec.trim().split_once('-') {
+            Some(("", _)) => Some(u64::MAX),
+            Some((start, _)) => start.trim().parse().ok(),
+            None => None,
+        })
+        .collect::<Option<Vec<u64>>>()
+        .and_then(|starts| starts.into_iter().min())
+        .unwrap_or(0)
+}
+
+/// Request extension naming the file a download handler served, picked up
+/// by `audit_downloads`
+#[derive(Debug, Clone)]
+pub struct ServedFile(pub String);
+
+/// Longest header value kept in the audit log
+const MAX_AUDITED_HEADER: usize = 512;
+
+fn audited_header(req: &ServiceRequest, name: &HeaderName) -> Option<String> {
+    let value = req.headers().get(name)?.to_str().ok()?;
+    let end = value
+        .char_indices()
+        .nth(MAX_AUDITED_HEADER)
+        .map_or(value.len(), |(i, _)| i);
+    Some(value[..end].to_string())
+}
+
+fn outcome_of(e: &actix_web::Error) -> DownloadOutcome {
+    e.as_error::<ServiceError>()
+        .map_or(DownloadOutcome::Error, DownloadOutcome::from)
+}
+
+/// Writes the event off the request path, a failed write is only logged
+fn record_event(db: web::Data<Db>, event: NewDownloadEvent) {
+    actix_web::rt::spawn(async move {
+        if let Err(e) = db
+            .blocking(move |db| db.record_download_event(&event))
+            .await
+        {
+            log::warn!("could not record download event: {e}");
+        }
+    });
+}
+
+/// Middleware for the download routes, outermost: records every request
+/// in the download audit log. Failed requests are recorded right away,
+/// served ones once the body is sent or the client gave up.
+///
+/// # Errors
+///
+/// Only passes on errors of the wrapped handler
+pub async fn audit_downloads(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(db) = req.app_data::<web::Data<Db>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let mut event = NewDownloadEvent {
+        slug: req.match_info().get("slug").unwrap_or_default().to_string(),
+        ip: client_ip(req.request()),
+        user_agent: audited_header(&req, &USER_AGENT),
+        range: audited_header(&req, &RANGE),
+        ..NewDownloadEvent::default()
+    };
+
+    let res = match next.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            event.outcome = Some(outcome_of(&e));
+            record_event(db, event);
+            return Err(e);
+        }
+    };
+    event.file_id = res
+        .request()
+        .extensions()
+        .get::<ServedFile>()
+        .map(|f| f.0.clone());
+    let failed = match res.response().error() {
+        Some(e) => Some(outcome_of(e)),
+        None if res.status().is_client_error() || res.status().is_server_error() => {
+            Some(DownloadOutcome::Error)
+        }
+        None => None,
+    };
+    if failed.is_some() {
+        event.outcome = failed;
+        record_event(db, event);
+        return Ok(res.map_into_boxed_body());
+    }
+    Ok(res.map_body(|_, body| Audited::new(body.boxed(), db, event).boxed()))
+}
+
+/// Response body counting what reaches the client, records its event when
+/// dropped: `Success` if it ran to the end, `Aborted` otherwise
+pub struct Audited {
+    body: BoxBody,
+    db: web::Data<Db>,
+    event: Option<NewDownloadEvent>,
+}
+
+impl Audited {
+    #[must_use]
+    pub fn new(body: BoxBody, db: web::Data<Db>, event: NewDownloadEvent) -> Self {
+        Self {
+            body,
+            db,
+            event: Some(event),
+        }
+    }
+
+    fn finish(&mut self, outcome: DownloadOutcome) {
+        if let Some(event) = &mut self.event {
+            event.outcome.get_or_insert(outcome);
+        }
+    }
+}
+
+impl MessageBody for Audited {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.body).poll_next(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(event) = &mut this.event {
+                    event.bytes_sent += chunk.len() as u64;
+                }
+            }
+            Poll::Ready(Some(Err(_))) => this.finish(DownloadOutcome::Error),
+            Poll::Ready(None) => this.finish(DownloadOutcome::Success),
+            Poll::Pending => {}
+        }
+        polled
+    }
+}
+
+impl Drop for Audited {
+    fn drop(&mut self) {
+        self.finish(DownloadOutcome::Aborted);
+        if let Some(event) = self.event.take() {
+            record_event(self.db.clone(), event);
+        }
+    }
+}
+
+/// Response body of a file served in a download session, hands the part
+/// of the session's reservation that wasn't sent back when dropped
+pub struct Reserved {
+    body: BoxBody,
+    db: web::Data<Db>,
+    session: String,
+    abs_path: String,
+    unsent: i64,
+}
+
+impl Reserved {
+    #[must_use]
+    pub fn new(body: BoxBody, db: web::Data<Db>, target: &DownloadTarget) -> Self {
+        Self {
+            body,
+            db,
+            session: target.session.clone(),
+            abs_path: target.abs_path.clone(),
+            unsent: target.reserved,
+        }
+    }
+}
+
+impl MessageBody for Reserved {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            this.unsent -= i64::try_from(chunk.len()).unwrap_or(i64::MAX);
+        }
+        polled
+    }
+}
+
+impl Drop for Reserved {
+    fn drop(&mut self) {
+        if self.unsent <= 0 {
+            return;
+        }
+        let (db, session, abs_path) = (
+            self.db.clone(),
+            std::mem::take(&mut self.session),
+            std::mem::take(&mut self.abs_path),
+        );
+        let unsent = self.unsent;
+        actix_web::rt::spawn(async move {
+            if let Err(e) = db
+                .blocking(move |db| db.return_download_bytes(&session, &abs_path, unsent))
+                .await
+            {
+                log::warn!("could not return unsent download bytes: {e}");
+            }
+        });
+    }
+}
+
+/// Column names of `events_csv`
+const EVENT_CSV_HEADER: &str = "id,slug,file_id,at,ip,user_agent,bytes_sent,range,outcome";
+
+/// Quotes a CSV field if needed. Fields a spreadsheet would run as a
+/// formula get a leading `'`, the user agent is whatever the client sent.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Download events as CSV with a header row, for export
+#[must_use]
+pub fn events_csv(events: &[DownloadEvent]) -> String {
+    let mut out = String::from(EVENT_CSV_HEADER);
+    out.push_str("\r\n");
+    for e in events {
+        let fields = [
+            e.id.to_string(),
+            csv_field(&e.slug),
+            csv_field(e.file_id.as_deref().unwrap_or_default()),
+            csv_field(&e.at),
+            csv_field(e.ip.as_deref().unwrap_or_default()),
+            csv_field(e.user_agent.as_deref().unwrap_or_default()),
+            e.bytes_sent.to_string(),
+            csv_field(e.range.as_deref().unwrap_or_default()),
+            e.outcome.as_str().to_string(),
+        ];
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
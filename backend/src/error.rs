@@ -0,0 +1,144 @@
+// src/error.rs
+use actix_web::{
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Everything the DB layer and the handlers can fail with.
+/// Each variant maps to one HTTP status and a JSON body `{ "error", "message" }`.
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    #[error("not found")]
+    NotFound,
+    #[error("invalid password")]
+    BadPassword,
+    #[error("login required")]
+    Unauthorized,
+    #[error("share has expired")]
+    Expired,
+    #[error("share download limit reached")]
+    LimitReached,
+    #[error("shared file no longer exists")]
+    FileMissing,
+    #[error("shared file changed since it was shared")]
+    FileChanged,
+    #[error("path is not shareable: {}", .0.display())]
+    PathRejected(PathBuf),
+    #[error("invalid request: {0}")]
+    Invalid(String),
+    #[error("upload is larger than the {limit} byte limit")]
+    TooLarge { limit: u64 },
+    #[error("upload conflict: {0}")]
+    UploadConflict(String),
+    #[error("too many failed password attempts, retry in {retry_after}s")]
+    TooManyAttempts { retry_after: u64 },
+    #[error("{reason}, retry in {retry_after}s")]
+    RateLimited {
+        reason: &'static str,
+        retry_after: u64,
+    },
+    #[error("invalid signed URL: {0}")]
+    BadSignature(&'static str),
+    #[error("could not generate a free slug")]
+    SlugCollision,
+    #[error("slug {0:?} is already taken")]
+    SlugTaken(String),
+    #[error("password hashing failed")]
+    Hash,
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("database schema v{found} is newer than this binary supports (v{supported})")]
+    SchemaTooNew { found: i64, supported: i64 },
+    #[error("database pool error: {0}")]
+    Pool(#[from] r2d2::Error),
+    #[error("blocking task failed: {0}")]
+    Blocking(#[from] actix_web::error::BlockingError),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl ServiceError {
+    /// Stable machine readable name, sent as `error` in the JSON body
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::BadPassword => "bad_password",
+            Self::Unauthorized => "unauthorized",
+            Self::Expired => "expired",
+            Self::LimitReached => "limit_reached",
+            Self::FileMissing => "file_missing",
+            Self::FileChanged => "file_changed",
+            Self::PathRejected(_) => "path_rejected",
+            Self::Invalid(_) => "invalid",
+            Self::TooLarge { .. } => "too_large",
+            Self::UploadConflict(_) => "upload_conflict",
+            Self::TooManyAttempts { .. } => "too_many_attempts",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::BadSignature(_) => "bad_signature",
+            Self::SlugCollision => "slug_collision",
+            Self::SlugTaken(_) => "slug_taken",
+            Self::Hash => "hash",
+            Self::Io(_) => "io",
+            Self::Sqlite(_) => "sqlite",
+            Self::SchemaTooNew { .. } => "schema_too_new",
+            Self::Pool(_) => "pool",
+            Self::Blocking(_) => "blocking",
+        }
+    }
+}
+
+impl ResponseError for ServiceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::BadPassword | Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Expired | Self::LimitReached | Self::FileMissing => StatusCode::GONE,
+            Self::FileChanged | Self::UploadConflict(_) | Self::SlugTaken(_) => {
+                StatusCode::CONFLICT
+            }
+            Self::PathRejected(_) | Self::BadSignature(_) => StatusCode::FORBIDDEN,
+            Self::Invalid(_) => StatusCode::BAD_REQUEST,
+            Self::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyAttempts { .. } | Self::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::SlugCollision
+            | Self::Hash
+            | Self::Io(_)
+            | Self::Sqlite(_)
+            | Self::SchemaTooNew { .. }
+            | Self::Pool(_)
+            | Self::Blocking(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        // Don't leak paths or SQL to clients on internal failures
+        let message = if status.is_server_error() {
+            log::error!("{self}");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+
+        let mut res = HttpResponse::build(status);
+        if let Self::TooManyAttempts { retry_after } | Self::RateLimited { retry_after, .. } = self
+        {
+            res.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        res.json(ErrorBody {
+            error: self.code(),
+            message,
+        })
+    }
+}
@@ -0,0 +1,90 @@
+// src/hashing.rs
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+
+/// Files up to this size are hashed while registering, bigger ones in the background
+pub const INLINE_HASH_LIMIT: i64 = 16 * 1024 * 1024;
+
+/// Threads running background hash jobs, further jobs wait their turn
+pub const HASH_WORKERS: usize = 2;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+const CHUNK: usize = 64 * 1024;
+
+/// Streams the file through SHA-256, returns lowercase hex
+///
+/// # Errors
+///
+/// Will error if the file can't be opened or read
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// `Repr-Digest` value (RFC 9530) for a stored hex digest
+#[must_use]
+pub fn repr_digest(sha256_hex: &str) -> Option<String> {
+    let raw = hex::decode(sha256_hex).ok()?;
+    Some(format!("sha-256=:{}:", STANDARD.encode(raw)))
+}
+
+/// Legacy `Digest` value (RFC 3230) for a stored hex digest
+#[must_use]
+pub fn legacy_digest(sha256_hex: &str) -> Option<String> {
+    let raw = hex::decode(sha256_hex).ok()?;
+    Some(format!("sha-256={}", STANDARD.encode(raw)))
+}
+
+/// Queues `job` for the hash workers, which are started on first use and
+/// shared by the whole process
+///
+/// # Errors
+///
+/// Will error if no worker thread could be started
+pub fn queue_job(job: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    static QUEUE: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+    let tx = match QUEUE.get() {
+        Some(tx) => tx,
+        None => {
+            let tx = start_workers()?;
+            QUEUE.get_or_init(|| tx)
+        }
+    };
+    tx.send(Box::new(job))
+        .map_err(|_| io::Error::other("hash workers are gone"))
+}
+
+fn start_workers() -> io::Result<mpsc::Sender<Job>> {
+    let (tx, rx) = mpsc::channel::<Job>();
+    let rx = Arc::new(Mutex::new(rx));
+    for i in 0..HASH_WORKERS {
+        let rx = Arc::clone(&rx);
+        std::thread::Builder::new()
+            .name(format!("hash-{i}"))
+            .spawn(move || loop {
+                let job = match rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => return,
+                }
+            })?;
+    }
+    Ok(tx)
+}
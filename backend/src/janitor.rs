@@ -0,0 +1,119 @@
+// src/janitor.rs
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db::Db;
+use crate::error::ServiceError;
+use crate::policy::ExpiredSharePolicy;
+use crate::storage::Storage;
+
+/// What a janitor run cleans up, see `Config::janitor_settings`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JanitorSettings {
+    pub expired_shares: ExpiredSharePolicy,
+    /// File rows younger than this are kept even without a share
+    pub orphan_grace_secs: u64,
+    /// Also delete orphaned uploads from the storage dir
+    pub purge_orphan_uploads: bool,
+}
+
+/// What one run removed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct JanitorReport {
+    pub dead_shares: usize,
+    pub orphan_files: usize,
+    pub deleted_uploads: usize,
+    pub stale_uploads: usize,
+    pub expired_sessions: usize,
+    pub vacuumed: bool,
+}
+
+/// Periodic cleanup of the DB and the upload storage dir
+#[derive(Debug, Clone)]
+pub struct Janitor {
+    db: Db,
+    storage: Storage,
+    settings: JanitorSettings,
+}
+
+impl Janitor {
+    #[must_use]
+    pub fn new(db: Db, storage: Storage, settings: JanitorSettings) -> Self {
+        Self {
+            db,
+            storage,
+            settings,
+        }
+    }
+
+    /// One pass over everything, blocking. Safe next to a running server:
+    /// only partial files of expired sessions are touched.
+    ///
+    /// # Errors
+    ///
+    /// The first DB failure, the run stops there
+    pub fn run_once(&self, vacuum: bool) -> Result<JanitorReport, ServiceError> {
+        let mut report = JanitorReport {
+            dead_shares: self
+                .db
+                .purge_dead_shares(self.settings.expired_shares)?
+                .len(),
+            ..JanitorReport::default()
+        };
+
+        let orphans = self
+            .db
+            .purge_orphan_files(self.settings.orphan_grace_secs)?;
+        report.orphan_files = orphans.len();
+        if self.settings.purge_orphan_uploads {
+            for path in &orphans {
+                if self.storage.discard(Path::new(path)) {
+                    report.deleted_uploads += 1;
+                }
+            }
+        }
+
+        let stale = self.db.purge_stale_upload_sessions()?;
+        for id in &stale {
+            self.storage.remove_session(id);
+        }
+        report.stale_uploads = stale.len();
+        report.expired_sessions = self.db.purge_expired_sessions()?;
+
+        self.db.optimize()?;
+        if vacuum {
+            self.db.vacuum()?;
+            report.vacuumed = true;
+        }
+        Ok(report)
+    }
+
+    /// Runs every `interval` on the actix runtime, vacuuming at most once
+    /// per `vacuum_every` (never if zero). The first run is one interval
+    /// after the start, startup does its own sweep.
+    pub fn spawn(self, interval: Duration, vacuum_every: Duration) {
+        actix_web::rt::spawn(async move {
+            let mut ticks = actix_web::rt::time::interval_at(
+                actix_web::rt::time::Instant::now() + interval,
+                interval,
+            );
+            let mut last_vacuum = Instant::now();
+            loop {
+                ticks.tick().await;
+                let vacuum = !vacuum_every.is_zero() && last_vacuum.elapsed() >= vacuum_every;
+                let janitor = self.clone();
+                match actix_web::web::block(move || janitor.run_once(vacuum)).await {
+                    Ok(Ok(report)) => {
+                        if vacuum {
+                            last_vacuum = Instant::now();
+                        }
+                        log::info!("janitor: {report:?}");
+                    }
+                    Ok(Err(e)) => log::error!("janitor run failed: {e}"),
+                    Err(e) => log::error!("janitor run failed: {e}"),
+                }
+            }
+        });
+    }
+}
@@ -1 +1,14 @@
+pub mod archive;
+pub mod auth;
+pub mod config;
 pub mod db;
+pub mod download;
+pub mod error;
+pub mod hashing;
+pub mod janitor;
+pub mod migrations;
+pub mod policy;
+pub mod signing;
+pub mod slug;
+pub mod storage;
+pub mod throttle;
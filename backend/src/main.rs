@@ -1,17 +1,41 @@
 use actix_files::NamedFile;
-use actix_web::http::header::{Charset, ExtendedValue};
-use actix_web::middleware::Logger;
+use actix_multipart::{Field, Multipart};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header::{Charset, ExtendedValue, HeaderValue, ACCEPT_RANGES, ETAG};
+use actix_web::middleware::{from_fn, Logger};
 use actix_web::{
-    delete,
-    error::{ErrorInternalServerError, ErrorUnauthorized},
-    get,
+    delete, get,
     http::header::{ContentDisposition, DispositionParam, DispositionType},
-    post, web, App, HttpResponse, HttpServer, Responder, Result,
+    patch, post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
-use file_serve::db::{CreateShareReq, Db, FileEntry, PublicShare, Share};
+use clap::Parser;
+use file_serve::archive::{stream_archive, ArchiveFormat, ArchiveSource, DirEntry as ArchiveEntry};
+use file_serve::auth::{
+    audit_admin, audit_params, audit_target, require_admin, session_token, AuditNote,
+    SESSION_COOKIE,
+};
+use file_serve::config::{Cli, Command, Config, LogFormat};
+use file_serve::db::{
+    AdminAuditPage, AdminAuditQuery, ArchivedShare, CreateShareReq, Credential, Db, DigestCheck,
+    DirListing, DownloadEventPage, DownloadEventQuery, DownloadRequest, DownloadTarget, FileEntry,
+    LockoutScope, PasswordFailures, PublicShare, Share, ShareKind, UpdateShareReq, UploadSession,
+    DOWNLOAD_SESSION_HOURS, UNLOCK_TOKEN_MINUTES,
+};
+use file_serve::download::{
+    audit_downloads, client_ip, download_session, entity_tag, events_csv, range_start,
+    strip_stale_range, unlock_token, Reserved, ServedFile, DOWNLOAD_SESSION_COOKIE, UNLOCK_COOKIE,
+};
+use file_serve::error::ServiceError;
+use file_serve::hashing;
+use file_serve::janitor::Janitor;
+use file_serve::signing::{SigningKey, UrlSignature, UrlSigner};
+use file_serve::storage::{sanitize_name, Storage};
+use file_serve::throttle::{throttle_downloads, Throttle};
+use futures_util::{StreamExt, TryStreamExt};
 
 #[get("/")]
 async fn hello() -> impl Responder {
@@ -23,150 +47,1431 @@ async fn hello() -> impl Responder {
 // Structs
 #[derive(Deserialize)]
 struct DownloadQuery {
-    // Optional: password or not
-    password: Option<String>,
+    /// Unlock token of a password protected share, the cookie also works
+    token: Option<String>,
+    // Signed URL, see `UrlSignature`
+    expires: Option<i64>,
+    kid: Option<i64>,
+    sig: Option<String>,
+    /// Archive format for directory shares, zip by default
+    #[serde(default)]
+    format: ArchiveFormat,
 }
 
-#[get("/api/download/{slug}")]
-async fn download_file(path: web::Path<String>, q: web::Query<DownloadQuery>) -> Result<NamedFile> {
-    let slug = path.into_inner();
-    let password = q.password.as_deref().unwrap_or("");
-
-    let db = Db::new().map_err(ErrorInternalServerError)?;
-
-    match db.get_download_target(&slug, password) {
-        Ok(Some((abs_path, file_name))) => {
-            let path: PathBuf = abs_path.into();
-            let mut file = NamedFile::open(path).map_err(ErrorInternalServerError)?;
-
-            // Set Content-type
-            let ct = mime_guess::from_path(file.path()).first_or_octet_stream();
-            file = file.set_content_type(ct);
-
-            // Force download with UTF-8 filename
-            file = file.set_content_disposition(ContentDisposition {
-                disposition: DispositionType::Attachment,
-                parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
-                    charset: Charset::Ext("UTF-8".to_owned()),
-                    language_tag: None,
-                    value: file_name.as_bytes().to_vec(),
-                })],
-            });
+impl DownloadQuery {
+    /// Checks the URL signature, if the URL is signed, and returns its
+    /// expiry. Done before any DB work so forged or stale links are turned
+    /// away cheaply.
+    fn verify_signature(
+        &mut self,
+        signer: &UrlSigner,
+        slug: &str,
+    ) -> Result<Option<i64>, ServiceError> {
+        let signature = match (self.expires.take(), self.kid.take(), self.sig.take()) {
+            (None, None, None) => return Ok(None),
+            (Some(expires), Some(kid), Some(sig)) => UrlSignature { expires, kid, sig },
+            _ => return Err(ServiceError::BadSignature("needs expires, kid and sig")),
+        };
+        signer
+            .verify(slug, &signature)
+            .map(|()| Some(signature.expires))
+    }
+}
 
-            Ok(file)
+/// Credential of a public request; passwords only go to `unlock_share`
+fn credential(token: Option<&str>, signed: Option<i64>) -> Credential<'_> {
+    match signed {
+        Some(expires) => Credential::SignedUrl { expires },
+        None => token.map_or(Credential::None, Credential::Token),
+    }
+}
+
+/// `attachment` disposition with a UTF-8 file name
+fn attachment(file_name: &str) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_owned()),
+            language_tag: None,
+            value: file_name.as_bytes().to_vec(),
+        })],
+    }
+}
+
+/// Lets the client resume without using up another download
+fn download_session_cookie(slug: &str, session: String) -> Cookie<'static> {
+    Cookie::build(DOWNLOAD_SESSION_COOKIE, session)
+        .path(format!("/api/download/{slug}"))
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::hours(
+            DOWNLOAD_SESSION_HOURS,
+        ))
+        .finish()
+}
+
+/// Directory shares and bundles: an archive generated while it's sent,
+/// so there is no length, ETag or range support
+fn archive_response(
+    db: &Db,
+    target: DownloadTarget,
+    format: ArchiveFormat,
+) -> Result<HttpResponse, ServiceError> {
+    let (source, stats_for) = if target.kind == ShareKind::Bundle {
+        let files = target.members.iter().map(bundle_entry).collect();
+        let name = target.file_name.clone();
+        (ArchiveSource::Files { name, files }, None)
+    } else {
+        let root = target.abs_path.clone().into();
+        let policy = db.path_policy().clone();
+        (
+            ArchiveSource::Dir { root, policy },
+            Some(target.file_id.clone()),
+        )
+    };
+    // Only a directory's totals can drift between scans
+    let stats_db = db.clone();
+    let body = stream_archive(format, source, move |stats| {
+        let Some(file_id) = stats_for else { return };
+        if let Err(e) = stats_db.set_dir_stats(&file_id, stats) {
+            log::warn!("could not record directory totals: {e}");
         }
-        Ok(None) => Err(actix_web::error::ErrorNotFound("share not found")),
-        Err(e) => {
-            //UnwindingPanic sentinel for incorrect password
-            let is_auth_error = matches!(e, rusqlite::Error::UnwindingPanic);
-            if is_auth_error {
-                Err(ErrorUnauthorized("Invalid password"))
-            } else {
-                Err(ErrorInternalServerError(e))
+    })?;
+
+    // No session cookie: an archive can't be resumed, and its session
+    // would let the share's files be fetched again without counting
+    let file_name = format!("{}.{}", target.file_name, format.extension());
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(attachment(&file_name))
+        .insert_header((ACCEPT_RANGES, "none"))
+        .streaming(body))
+}
+
+fn bundle_entry(file: &FileEntry) -> ArchiveEntry {
+    ArchiveEntry {
+        name: file.name.clone(),
+        source: file.abs_path.clone().into(),
+        is_dir: false,
+        size: u64::try_from(file.size_bytes).unwrap_or(0),
+        mtime: file.mtime,
+    }
+}
+
+#[get(
+    "/api/download/{slug}",
+    wrap = "from_fn(strip_stale_range)",
+    wrap = "from_fn(throttle_downloads)",
+    wrap = "from_fn(audit_downloads)"
+)]
+async fn download_file(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    signer: web::Data<UrlSigner>,
+    path: web::Path<String>,
+    q: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let slug = path.into_inner();
+    let mut q = q.into_inner();
+    let signed = q.verify_signature(&signer, &slug)?;
+    let token = unlock_token(&req, q.token);
+    let session = download_session(&req);
+    let offset = range_start(&req);
+
+    let target = {
+        let slug = slug.clone();
+        db.blocking(move |db| {
+            let credential = credential(token.as_deref(), signed);
+            let request = DownloadRequest {
+                session: session.as_deref(),
+                offset,
+            };
+            db.get_download_target(&slug, credential, request)
+        })
+        .await?
+    };
+    req.extensions_mut()
+        .insert(ServedFile(target.file_id.clone()));
+    let format = q.format;
+
+    if target.kind != ShareKind::File {
+        return archive_response(&db, target, format);
+    }
+    serve_file(&req, db, &slug, target).await
+}
+
+/// One file of a bundle by name, or of a directory share by its path
+#[get(
+    "/api/download/{slug}/{path:.*}",
+    wrap = "from_fn(strip_stale_range)",
+    wrap = "from_fn(throttle_downloads)",
+    wrap = "from_fn(audit_downloads)"
+)]
+async fn download_member(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    signer: web::Data<UrlSigner>,
+    path: web::Path<(String, String)>,
+    q: web::Query<DownloadQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let (slug, member) = path.into_inner();
+    let mut q = q.into_inner();
+    // A signed link to the share also covers its files
+    let signed = q.verify_signature(&signer, &slug)?;
+    let token = unlock_token(&req, q.token);
+    let session = download_session(&req);
+    let offset = range_start(&req);
+
+    let target = {
+        let slug = slug.clone();
+        db.blocking(move |db| {
+            let credential = credential(token.as_deref(), signed);
+            let request = DownloadRequest {
+                session: session.as_deref(),
+                offset,
+            };
+            db.get_member_target(&slug, &member, credential, request)
+        })
+        .await?
+    };
+    req.extensions_mut()
+        .insert(ServedFile(target.file_id.clone()));
+    serve_file(&req, db, &slug, target).await
+}
+
+/// Sends a single file with ranges, validators and digests
+async fn serve_file(
+    req: &HttpRequest,
+    db: web::Data<Db>,
+    slug: &str,
+    target: DownloadTarget,
+) -> Result<HttpResponse, ServiceError> {
+    let path = PathBuf::from(&target.abs_path);
+    // ETag comes from the recorded size + mtime instead, see `entity_tag`
+    let mut file = NamedFile::open_async(path).await?.use_etag(false);
+
+    // Set Content-type
+    let ct = mime_guess::from_path(file.path()).first_or_octet_stream();
+    file = file.set_content_type(ct);
+
+    // Force download with UTF-8 filename
+    file = file.set_content_disposition(attachment(&target.file_name));
+
+    let mut res = file.into_response(req);
+    if let Some(etag) = entity_tag(target.size_bytes, target.mtime) {
+        res.headers_mut().insert(
+            ETAG,
+            HeaderValue::from_str(&etag.to_string()).map_err(std::io::Error::other)?,
+        );
+    }
+    // Repr-Digest describes the whole file, so it also holds for 206 responses
+    if let Some(sha) = &target.sha256 {
+        for (name, value) in [
+            ("repr-digest", hashing::repr_digest(sha)),
+            ("digest", hashing::legacy_digest(sha)),
+        ] {
+            if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                res.headers_mut().insert(
+                    actix_web::http::header::HeaderName::from_static(name),
+                    value,
+                );
             }
         }
     }
+
+    res.add_cookie(&download_session_cookie(slug, target.session.clone()))
+        .map_err(std::io::Error::other)?;
+
+    Ok(res
+        .map_body(|_, body| Reserved::new(body, db, &target))
+        .map_into_boxed_body())
 }
 
 #[get("/api/share/{slug}")]
 async fn get_public_share(
+    db: web::Data<Db>,
     path: web::Path<String>,
-) -> Result<web::Json<PublicShare>, actix_web::Error> {
+) -> Result<web::Json<PublicShare>, ServiceError> {
     let slug = path.into_inner();
-    let db = Db::new().map_err(ErrorInternalServerError)?;
 
-    match db
-        .get_public_share(&slug)
-        .map_err(ErrorInternalServerError)?
-    {
-        Some(ps) => Ok(web::Json(ps)),
-        None => Err(actix_web::error::ErrorNotFound("share not found")),
+    db.blocking(move |db| db.get_public_share(&slug))
+        .await?
+        .map(web::Json)
+        .ok_or(ServiceError::NotFound)
+}
+
+#[derive(Deserialize)]
+struct UnlockReq {
+    password: String,
+}
+
+/// Trades a share's password for an unlock token, also set as a cookie,
+/// so the password never has to be part of a download URL
+#[post("/api/share/{slug}/unlock")]
+async fn unlock_share(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    path: web::Path<String>,
+    body: web::Json<UnlockReq>,
+) -> Result<HttpResponse, ServiceError> {
+    let slug = path.into_inner();
+    let password = body.into_inner().password;
+    let client = client_ip(&req);
+
+    let unlock = {
+        let slug = slug.clone();
+        db.blocking(move |db| {
+            db.guard_password(&slug, client.as_deref(), |db| {
+                db.unlock_share(&slug, &password)
+            })
+        })
+        .await?
+    };
+
+    let mut res = HttpResponse::Ok();
+    for path in [
+        format!("/api/download/{slug}"),
+        format!("/api/share/{slug}"),
+    ] {
+        res.cookie(
+            Cookie::build(UNLOCK_COOKIE, unlock.token.clone())
+                .path(path)
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::minutes(
+                    UNLOCK_TOKEN_MINUTES,
+                ))
+                .finish(),
+        );
     }
+    Ok(res.json(unlock))
+}
+
+#[derive(Deserialize)]
+struct TreeQuery {
+    #[serde(default)]
+    path: String,
+    token: Option<String>,
+}
+
+#[get("/api/share/{slug}/tree")]
+async fn get_share_tree(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    path: web::Path<String>,
+    q: web::Query<TreeQuery>,
+) -> Result<web::Json<DirListing>, ServiceError> {
+    let slug = path.into_inner();
+    let TreeQuery { path, token } = q.into_inner();
+    let token = unlock_token(&req, token);
+
+    db.blocking(move |db| db.list_share_dir(&slug, &path, credential(token.as_deref(), None)))
+        .await
+        .map(web::Json)
 }
 
 // ——— Admin section ———
 
 // Structs
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct CreateFileReq {
     abs_path: String,
 }
 
+/// A new share plus its public link, when `public_base_url` is set
+#[derive(Serialize)]
+struct ShareCreated {
+    #[serde(flatten)]
+    share: Share,
+    url: Option<String>,
+}
+
+/// Result of `/admin/upload`, `share` and `url` only if a share was asked for
+#[derive(Serialize)]
+struct Uploaded {
+    file: FileEntry,
+    share: Option<Share>,
+    url: Option<String>,
+}
+
+/// Share options of an upload: text fields next to the multipart file,
+/// or the JSON body finalizing a chunked upload
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UploadOptions {
+    share: bool,
+    password: Option<String>,
+    expires_at: Option<String>,
+    max_downloads: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CreateUploadReq {
+    file_name: String,
+    size_bytes: i64,
+}
+
+#[derive(Deserialize)]
+struct LoginReq {
+    username: String,
+    password: String,
+}
+
 // Endpoints
 
-#[get("/admin/shares")]
-async fn get_shares() -> Result<web::Json<Vec<Share>>, actix_web::Error> {
-    let db = Db::new().map_err(ErrorInternalServerError)?;
-    let shares = db.list_shares().map_err(ErrorInternalServerError)?;
+// Only admin route outside the auth guard
+#[post("/login")]
+async fn login(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    body: web::Json<LoginReq>,
+) -> Result<HttpResponse, ServiceError> {
+    let LoginReq { username, password } = body.into_inner();
+    // Not logged in yet, the attempted name stands in for the actor
+    req.extensions_mut().insert(AuditNote {
+        actor: Some(username.clone()),
+        params: Some(serde_json::json!({ "username": username })),
+        ..AuditNote::default()
+    });
+    let session = db
+        .blocking(move |db| db.login(&username, &password))
+        .await?
+        .ok_or(ServiceError::BadPassword)?;
+
+    let cookie = Cookie::build(SESSION_COOKIE, session.token.clone())
+        .path("/admin")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+
+    Ok(HttpResponse::Ok().cookie(cookie).json(session))
+}
+
+#[post("/logout")]
+async fn logout(db: web::Data<Db>, req: HttpRequest) -> Result<HttpResponse, ServiceError> {
+    if let Some(token) = session_token(&req) {
+        db.blocking(move |db| db.delete_session(&token)).await?;
+    }
+
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/admin").finish();
+    cookie.make_removal();
+    Ok(HttpResponse::NoContent().cookie(cookie).finish())
+}
+
+#[get("/shares")]
+async fn get_shares(db: web::Data<Db>) -> Result<web::Json<Vec<Share>>, ServiceError> {
+    let shares = db.blocking(Db::list_shares).await?;
+    Ok(web::Json(shares))
+}
+
+/// Shares the janitor retired with `on_share_expired = "archive"`
+#[get("/shares/archive")]
+async fn get_archived_shares(
+    db: web::Data<Db>,
+) -> Result<web::Json<Vec<ArchivedShare>>, ServiceError> {
+    let shares = db.blocking(Db::list_archived_shares).await?;
     Ok(web::Json(shares))
 }
 
-#[post("/admin/file")]
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Download audit log, filtered by `slug`, `file_id` and a `from` / `to`
+/// time range and paged with `limit` / `offset`. `format=csv` exports
+/// the page as a CSV file instead.
+#[get("/downloads")]
+async fn get_download_events(
+    db: web::Data<Db>,
+    query: web::Query<DownloadEventQuery>,
+    export: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let query = query.into_inner();
+    let page: DownloadEventPage = db
+        .blocking(move |db| db.list_download_events(&query))
+        .await?;
+    Ok(match export.format {
+        ExportFormat::Json => HttpResponse::Ok().json(page),
+        ExportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(attachment("downloads.csv"))
+            .body(events_csv(&page.events)),
+    })
+}
+
+#[post("/file")]
 async fn create_file(
+    req: HttpRequest,
+    db: web::Data<Db>,
     body: web::Json<CreateFileReq>,
-) -> Result<web::Json<FileEntry>, actix_web::Error> {
-    let db = Db::new().map_err(ErrorInternalServerError)?;
+) -> Result<web::Json<FileEntry>, ServiceError> {
+    audit_params(&req, &body);
+    let abs_path = body.into_inner().abs_path;
     let file = db
-        .create_or_get_file(&body.abs_path)
-        .map_err(ErrorInternalServerError)?;
+        .blocking(move |db| db.create_or_get_file(&abs_path))
+        .await?;
+    audit_target(&req, &file.id);
     Ok(web::Json(file))
 }
 
-#[delete("/admin/file/{file_id}")]
-async fn delete_file(path: web::Path<String>) -> Result<HttpResponse> {
+/// A registered file, `hash_state` tells whether a missing digest is
+/// still being worked on or failed
+#[get("/file/{file_id}")]
+async fn get_file(
+    db: web::Data<Db>,
+    path: web::Path<String>,
+) -> Result<web::Json<FileEntry>, ServiceError> {
     let file_id = path.into_inner();
-    let db = Db::new().map_err(ErrorInternalServerError)?;
-    let deleted = db.delete_file(&file_id).map_err(ErrorInternalServerError)?;
-    if deleted {
+    db.blocking(move |db| db.get_file(&file_id))
+        .await?
+        .map(web::Json)
+        .ok_or(ServiceError::NotFound)
+}
+
+#[delete("/file/{file_id}")]
+async fn delete_file(
+    db: web::Data<Db>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let file_id = path.into_inner();
+    if db.blocking(move |db| db.delete_file(&file_id)).await? {
         Ok(HttpResponse::NoContent().finish())
     } else {
-        Ok(HttpResponse::NotFound().finish())
+        Err(ServiceError::NotFound)
     }
 }
 
-#[post("/admin/share")]
+fn multipart_error(e: actix_multipart::MultipartError) -> ServiceError {
+    ServiceError::Invalid(format!("bad multipart body: {e}"))
+}
+
+/// Reads a small text field of the upload form
+async fn text_field(mut field: Field) -> Result<String, ServiceError> {
+    const MAX_TEXT: usize = 4096;
+    let mut buf = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+        if buf.len() + chunk.len() > MAX_TEXT {
+            return Err(ServiceError::Invalid("form field too long".into()));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    String::from_utf8(buf).map_err(|_| ServiceError::Invalid("form field is not UTF-8".into()))
+}
+
+/// Multipart upload into the storage dir. Takes one `file` part plus the
+/// optional fields `share`, `password`, `expires_at`, `max_downloads`;
+/// any share field implies `share=true`.
+#[post("/upload")]
+async fn upload_file(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    storage: web::Data<Storage>,
+    config: web::Data<Config>,
+    mut form: Multipart,
+) -> Result<web::Json<Uploaded>, ServiceError> {
+    let mut upload = None;
+    let mut opts = UploadOptions::default();
+
+    while let Some(mut field) = form.try_next().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name != "file" {
+            let value = text_field(field).await?;
+            let value = Some(value).filter(|v| !v.is_empty());
+            match name.as_str() {
+                "share" => opts.share = matches!(value.as_deref(), Some("true" | "1" | "on")),
+                "password" => opts.password = value,
+                "expires_at" => opts.expires_at = value,
+                "max_downloads" => {
+                    opts.max_downloads = value.map(|v| v.parse()).transpose().map_err(|_| {
+                        ServiceError::Invalid("max_downloads must be a number".into())
+                    })?;
+                }
+                other => return Err(ServiceError::Invalid(format!("unknown field {other:?}"))),
+            }
+            continue;
+        }
+
+        if upload.is_some() {
+            return Err(ServiceError::Invalid("only one file per upload".into()));
+        }
+        let file_name = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string)
+            .ok_or_else(|| ServiceError::Invalid("file part has no filename".into()))?;
+
+        let mut partial = storage.begin()?;
+        while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+            partial = web::block(move || partial.write(&chunk).map(|()| partial)).await??;
+        }
+        upload = Some((partial, file_name));
+    }
+
+    let (partial, file_name) =
+        upload.ok_or_else(|| ServiceError::Invalid("missing file part".into()))?;
+    let stored = {
+        let storage = storage.clone();
+        web::block(move || storage.persist(partial, &file_name)).await??
+    };
+    log::info!("stored upload {}", stored.display());
+
+    register_upload(&req, &db, &storage, &config, stored, opts)
+        .await
+        .map(web::Json)
+}
+
+/// Registers a stored upload like `POST /admin/file` / `/admin/share` would,
+/// removing it again if that fails
+async fn register_upload(
+    req: &HttpRequest,
+    db: &Db,
+    storage: &Storage,
+    config: &Config,
+    stored: PathBuf,
+    opts: UploadOptions,
+) -> Result<Uploaded, ServiceError> {
+    let want_share = opts.share
+        || opts.password.is_some()
+        || opts.expires_at.is_some()
+        || opts.max_downloads.is_some();
+    let abs_path = stored.to_string_lossy().into_owned();
+    if let Ok(mut params) = serde_json::to_value(&opts) {
+        params["abs_path"] = abs_path.clone().into();
+        audit_params(req, &params);
+    }
+    let registered = db
+        .blocking(move |db| {
+            if !want_share {
+                return Ok((db.create_or_get_file(&abs_path)?, None));
+            }
+            let share = db.create_share(&CreateShareReq {
+                abs_path,
+                password: opts.password,
+                expires_at: opts.expires_at,
+                max_downloads: opts.max_downloads,
+                ..Default::default()
+            })?;
+            let file = db.get_file(&share.file_id)?.ok_or(ServiceError::NotFound)?;
+            Ok((file, Some(share)))
+        })
+        .await;
+
+    let (file, share) = match registered {
+        Ok(done) => done,
+        Err(e) => {
+            // Don't keep bytes nothing refers to
+            storage.discard(&stored);
+            return Err(e);
+        }
+    };
+    let url = share.as_ref().and_then(|s| config.share_url(&s.slug));
+    audit_target(req, &file.id);
+    Ok(Uploaded { file, share, url })
+}
+
+// Chunked uploads: create a session with the total size, PATCH the bytes
+// in order with `Upload-Offset`, resume from `received_bytes` after an
+// interruption, then finalize to register the file.
+
+/// Header naming the offset a PATCH chunk starts at
+const UPLOAD_OFFSET: &str = "upload-offset";
+
+async fn live_upload(db: &Db, id: String) -> Result<UploadSession, ServiceError> {
+    db.blocking(move |db| db.get_upload_session(&id))
+        .await?
+        .ok_or(ServiceError::NotFound)
+}
+
+#[post("/uploads")]
+async fn create_upload(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    storage: web::Data<Storage>,
+    body: web::Json<CreateUploadReq>,
+) -> Result<web::Json<UploadSession>, ServiceError> {
+    audit_params(&req, &body);
+    let CreateUploadReq {
+        file_name,
+        size_bytes,
+    } = body.into_inner();
+    let file_name = sanitize_name(&file_name)
+        .ok_or_else(|| ServiceError::Invalid(format!("unusable file name {file_name:?}")))?;
+    if u64::try_from(size_bytes).is_ok_and(|size| size > storage.max_bytes()) {
+        return Err(ServiceError::TooLarge {
+            limit: storage.max_bytes(),
+        });
+    }
+
+    let (session, stale) = db
+        .blocking(move |db| {
+            let stale = db.purge_stale_upload_sessions()?;
+            Ok((db.create_upload_session(&file_name, size_bytes)?, stale))
+        })
+        .await?;
+    for id in &stale {
+        storage.remove_session(id);
+    }
+    if let Err(e) = storage.create_session(&session.id) {
+        let id = session.id.clone();
+        db.blocking(move |db| db.delete_upload_session(&id)).await?;
+        return Err(e.into());
+    }
+    Ok(web::Json(session))
+}
+
+#[get("/uploads/{id}")]
+async fn get_upload(
+    db: web::Data<Db>,
+    path: web::Path<String>,
+) -> Result<web::Json<UploadSession>, ServiceError> {
+    live_upload(&db, path.into_inner()).await.map(web::Json)
+}
+
+/// Appends the request body at `Upload-Offset`, which has to equal the
+/// session's `received_bytes`. Whatever arrived before an interruption is
+/// kept and recorded.
+#[patch("/uploads/{id}")]
+async fn append_upload(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    storage: web::Data<Storage>,
+    path: web::Path<String>,
+    mut body: web::Payload,
+) -> Result<web::Json<UploadSession>, ServiceError> {
+    let session = live_upload(&db, path.into_inner()).await?;
+    let offset: i64 = req
+        .headers()
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| ServiceError::Invalid("Upload-Offset header required".into()))?;
+    if offset != session.received_bytes {
+        return Err(ServiceError::UploadConflict(format!(
+            "upload is at offset {}",
+            session.received_bytes
+        )));
+    }
+
+    let mut partial = {
+        let (storage, id) = (storage.clone(), session.id.clone());
+        let (offset, size) = (session.received_bytes as u64, session.size_bytes as u64);
+        web::block(move || storage.resume_session(&id, offset, size)).await??
+    };
+    let mut outcome = Ok(());
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                outcome = Err(ServiceError::Invalid(format!("upload interrupted: {e}")));
+                break;
+            }
+        };
+        let (back, wrote) = web::block(move || {
+            let wrote = partial.write(&chunk);
+            (partial, wrote)
+        })
+        .await?;
+        partial = back;
+        if let Err(e) = wrote {
+            outcome = Err(e);
+            break;
+        }
+    }
+
+    let received = partial.written() as i64;
+    web::block(move || partial.sync()).await??;
+    let id = session.id;
+    let session = db
+        .blocking(move |db| db.set_upload_received(&id, received))
+        .await?
+        .ok_or(ServiceError::NotFound)?;
+    outcome.map(|()| web::Json(session))
+}
+
+#[post("/uploads/{id}/finalize")]
+async fn finalize_upload(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    storage: web::Data<Storage>,
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    body: Option<web::Json<UploadOptions>>,
+) -> Result<web::Json<Uploaded>, ServiceError> {
+    let session = live_upload(&db, path.into_inner()).await?;
+    if session.received_bytes != session.size_bytes {
+        return Err(ServiceError::UploadConflict(format!(
+            "only {} of {} bytes received",
+            session.received_bytes, session.size_bytes
+        )));
+    }
+
+    let stored = {
+        let (storage, session) = (storage.clone(), session.clone());
+        web::block(move || {
+            let size = session.size_bytes as u64;
+            let partial = storage.resume_session(&session.id, size, size)?;
+            storage.persist(partial, &session.file_name)
+        })
+        .await??
+    };
+    let id = session.id;
+    db.blocking(move |db| db.delete_upload_session(&id)).await?;
+    log::info!("stored upload {}", stored.display());
+
+    let opts = body.map(web::Json::into_inner).unwrap_or_default();
+    register_upload(&req, &db, &storage, &config, stored, opts)
+        .await
+        .map(web::Json)
+}
+
+#[delete("/uploads/{id}")]
+async fn cancel_upload(
+    db: web::Data<Db>,
+    storage: web::Data<Storage>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let id = path.into_inner();
+    let deleted = {
+        let id = id.clone();
+        db.blocking(move |db| db.delete_upload_session(&id)).await?
+    };
+    if !deleted {
+        return Err(ServiceError::NotFound);
+    }
+    storage.remove_session(&id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Re-hashes the file and compares it with the stored digest
+#[post("/file/{file_id}/verify")]
+async fn verify_file(
+    db: web::Data<Db>,
+    path: web::Path<String>,
+) -> Result<web::Json<DigestCheck>, ServiceError> {
+    let file_id = path.into_inner();
+    let check = db
+        .blocking(move |db| db.verify_file_digest(&file_id))
+        .await?;
+    Ok(web::Json(check))
+}
+
+#[post("/share")]
 async fn create_share(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    config: web::Data<Config>,
     body: web::Json<CreateShareReq>,
-) -> Result<web::Json<Share>, actix_web::Error> {
-    let db = Db::new().map_err(ErrorInternalServerError)?;
-    let share = db.create_share(&body).map_err(ErrorInternalServerError)?;
+) -> Result<web::Json<ShareCreated>, ServiceError> {
+    audit_params(&req, &body);
+    let new_share = body.into_inner();
+    let share = db.blocking(move |db| db.create_share(&new_share)).await?;
+    audit_target(&req, &share.slug);
+
+    let url = config.share_url(&share.slug);
+    Ok(web::Json(ShareCreated { share, url }))
+}
 
+/// Partial update: extend or clear `expires_at` and `max_downloads`,
+/// `reset_dl_count`, change the throttle overrides, set a new `password`
+/// or remove it with `null`
+#[patch("/share/{slug}")]
+async fn update_share(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    path: web::Path<String>,
+    body: web::Json<UpdateShareReq>,
+) -> Result<web::Json<Share>, ServiceError> {
+    audit_params(&req, &body);
+    let slug = path.into_inner();
+    let changes = body.into_inner();
+    let share = db
+        .blocking(move |db| db.update_share(&slug, &changes))
+        .await?;
     Ok(web::Json(share))
 }
 
-#[delete("/admin/share/{slug}")]
-async fn delete_share(path: web::Path<String>) -> Result<HttpResponse> {
+#[delete("/share/{slug}")]
+async fn delete_share(
+    db: web::Data<Db>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
     let slug = path.into_inner();
-    let db = Db::new().map_err(ErrorInternalServerError)?;
-    let deleted = db.delete_share(&slug).map_err(ErrorInternalServerError)?;
+    if db.blocking(move |db| db.delete_share(&slug)).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ServiceError::NotFound)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignUrlReq {
+    /// Lifetime in seconds, an hour by default
+    #[serde(default = "SignUrlReq::default_valid_secs")]
+    valid_secs: u64,
+}
+
+impl SignUrlReq {
+    fn default_valid_secs() -> u64 {
+        3600
+    }
+}
+
+#[derive(Serialize)]
+struct SignedUrl {
+    /// Download path with the signature, relative to the server
+    path: String,
+    /// Full link, if a public URL is configured
+    url: Option<String>,
+    /// Unix seconds
+    expires: i64,
+}
+
+/// Mints a signed download link for scripted downloads, which works
+/// without the share's password until it expires
+#[post("/share/{slug}/signed-url")]
+async fn sign_share_url(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    signer: web::Data<UrlSigner>,
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    body: web::Json<SignUrlReq>,
+) -> Result<web::Json<SignedUrl>, ServiceError> {
+    audit_params(&req, &body);
+    let slug = path.into_inner();
+    let share = {
+        let slug = slug.clone();
+        db.blocking(move |db| db.get_share(&slug)).await?
+    };
+    if share.is_none() {
+        return Err(ServiceError::NotFound);
+    }
+
+    let signature = signer.sign(&slug, body.valid_secs)?;
+    let path = format!("/api/download/{slug}?{}", signature.to_query());
+    let url = config
+        .public_base_url
+        .as_ref()
+        .map(|base| format!("{}{path}", base.trim_end_matches('/')));
+    Ok(web::Json(SignedUrl {
+        path,
+        url,
+        expires: signature.expires,
+    }))
+}
+
+#[get("/signing-keys")]
+async fn get_signing_keys(db: web::Data<Db>) -> Result<web::Json<Vec<SigningKey>>, ServiceError> {
+    let keys = db.blocking(Db::signing_keys).await?;
+    Ok(web::Json(keys))
+}
+
+/// Adds a new signing key; links signed with older keys keep working
+/// until those are deleted
+#[post("/signing-keys")]
+async fn rotate_signing_key(
+    req: HttpRequest,
+    db: web::Data<Db>,
+    signer: web::Data<UrlSigner>,
+) -> Result<web::Json<SigningKey>, ServiceError> {
+    let (key, keys) = db
+        .blocking(|db| Ok((db.rotate_signing_key()?, db.signing_keys()?)))
+        .await?;
+    audit_target(&req, key.id);
+    signer.set_keys(keys);
+    log::info!("rotated URL signing key, now signing with key {}", key.id);
+    Ok(web::Json(key))
+}
+
+/// Deletes a signing key, revoking every link signed with it
+#[delete("/signing-keys/{id}")]
+async fn delete_signing_key(
+    db: web::Data<Db>,
+    signer: web::Data<UrlSigner>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ServiceError> {
+    let id = path.into_inner();
+    let (deleted, keys) = db
+        .blocking(move |db| Ok((db.delete_signing_key(id)?, db.signing_keys()?)))
+        .await?;
+    signer.set_keys(keys);
     if deleted {
         Ok(HttpResponse::NoContent().finish())
     } else {
-        Ok(HttpResponse::NotFound().finish())
+        Err(ServiceError::NotFound)
+    }
+}
+
+#[get("/lockouts")]
+async fn get_lockouts(db: web::Data<Db>) -> Result<web::Json<Vec<PasswordFailures>>, ServiceError> {
+    let rows = db.blocking(Db::list_password_failures).await?;
+    Ok(web::Json(rows))
+}
+
+/// Lifts the lockout of a slug (`/lockouts/slug/{slug}`) or client IP
+/// (`/lockouts/ip/{ip}`)
+#[delete("/lockouts/{scope}/{key}")]
+async fn clear_lockout(
+    db: web::Data<Db>,
+    path: web::Path<(LockoutScope, String)>,
+) -> Result<HttpResponse, ServiceError> {
+    let (scope, key) = path.into_inner();
+    if db
+        .blocking(move |db| db.clear_password_failures(scope, &key))
+        .await?
+    {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ServiceError::NotFound)
     }
 }
 
+/// Admin audit trail, filtered by `actor`, `action` (method and route,
+/// e.g. `POST /admin/share`), `target`, a `q` to look for in the target
+/// or params and a `from` / `to` time range, paged with `limit` / `offset`
+#[get("/audit")]
+async fn get_admin_audit(
+    db: web::Data<Db>,
+    query: web::Query<AdminAuditQuery>,
+) -> Result<web::Json<AdminAuditPage>, ServiceError> {
+    let query = query.into_inner();
+    let page = db.blocking(move |db| db.list_admin_audit(&query)).await?;
+    Ok(web::Json(page))
+}
+
 // ——— Bind + Serve ———
 
+/// Creates the first admin from `FILE_SERVE_ADMIN_USER` / `FILE_SERVE_ADMIN_PASSWORD`
+/// if the DB has none yet
+fn bootstrap_admin(db: &Db) -> std::io::Result<()> {
+    if db.has_admin().map_err(std::io::Error::other)? {
+        return Ok(());
+    }
+
+    let username = std::env::var("FILE_SERVE_ADMIN_USER").unwrap_or_else(|_| "admin".into());
+    match std::env::var("FILE_SERVE_ADMIN_PASSWORD") {
+        Ok(password) if !password.is_empty() => {
+            db.create_admin(&username, &password)
+                .map_err(std::io::Error::other)?;
+            log::info!("created admin account '{username}'");
+        }
+        _ => log::warn!(
+            "no admin account exists; set FILE_SERVE_ADMIN_PASSWORD to create one, /admin is locked until then"
+        ),
+    }
+    Ok(())
+}
+
+/// Loads the URL signing keys, creating the first one on a fresh DB
+fn load_signer(db: &Db) -> std::io::Result<UrlSigner> {
+    let mut keys = db.signing_keys().map_err(std::io::Error::other)?;
+    if keys.is_empty() {
+        keys.push(db.rotate_signing_key().map_err(std::io::Error::other)?);
+        log::info!("created URL signing key");
+    }
+    Ok(UrlSigner::new(keys))
+}
+
+/// Drops expired upload sessions and partial files nothing refers to,
+/// e.g. one-shot uploads cut off by a restart
+fn sweep_uploads(db: &Db, storage: &Storage) -> std::io::Result<()> {
+    db.purge_stale_upload_sessions()
+        .map_err(std::io::Error::other)?;
+    let live = db.upload_session_ids().map_err(std::io::Error::other)?;
+    let removed = storage.sweep_partials(&live)?;
+    if removed > 0 {
+        log::info!("removed {removed} stale partial uploads");
+    }
+    Ok(())
+}
+
+fn init_logging(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            use std::io::Write;
+            let line = serde_json::json!({
+                "ts": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "msg": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    builder.init();
+}
+
+/// Every route, shared by the server and the HTTP tests
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(hello)
+        // Customer services
+        .service(get_public_share)
+        .service(get_share_tree)
+        .service(unlock_share)
+        .service(download_file)
+        .service(download_member)
+        // Admin service
+        .service(
+            web::scope("/admin")
+                .wrap(from_fn(audit_admin))
+                .service(login)
+                .service(
+                    web::scope("")
+                        .wrap(from_fn(require_admin))
+                        .service(logout)
+                        .service(get_shares)
+                        .service(get_archived_shares)
+                        .service(get_download_events)
+                        .service(create_file)
+                        .service(get_file)
+                        .service(delete_file)
+                        .service(verify_file)
+                        .service(upload_file)
+                        .service(create_upload)
+                        .service(get_upload)
+                        .service(append_upload)
+                        .service(finalize_upload)
+                        .service(cancel_upload)
+                        .service(create_share)
+                        .service(update_share)
+                        .service(delete_share)
+                        .service(sign_share_url)
+                        .service(get_signing_keys)
+                        .service(rotate_signing_key)
+                        .service(delete_signing_key)
+                        .service(get_lockouts)
+                        .service(clear_lockout)
+                        .service(get_admin_audit),
+                ),
+        );
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
-    HttpServer::new(|| {
+    let cli = Cli::parse();
+    let command = cli.command;
+    let config = Config::load(cli)?;
+    init_logging(config.log_format);
+
+    log::info!("using database {}", config.db_path.display());
+    // Before the policy, which adds the upload dir as a root
+    let storage = Storage::new(&config.upload_dir, config.max_upload_bytes)?;
+    log::info!("storing uploads in {}", storage.dir().display());
+    let policy = config.path_policy()?;
+    log::info!("shareable roots: {:?}", policy.roots());
+    let db = Db::open(&config.db_path)
+        .map_err(std::io::Error::other)?
+        .with_path_policy(policy)
+        .with_change_policy(config.on_file_change)
+        .with_lockout_policy(config.lockout_policy())
+        .with_slug_length(config.slug_length)
+        .with_slug_alphabet(config.slug_alphabet);
+    let janitor = Janitor::new(db.clone(), storage.clone(), config.janitor_settings());
+    if command == Some(Command::Janitor) {
+        let report = janitor.run_once(true).map_err(std::io::Error::other)?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    bootstrap_admin(&db)?;
+    sweep_uploads(&db, &storage)?;
+    let resumed = db.resume_hash_jobs().map_err(std::io::Error::other)?;
+    if resumed > 0 {
+        log::info!("resumed {resumed} pending hash jobs");
+    }
+    if config.janitor_interval_secs > 0 {
+        janitor.spawn(
+            Duration::from_secs(config.janitor_interval_secs),
+            Duration::from_secs(config.vacuum_interval_secs),
+        );
+    }
+    let signer = web::Data::new(load_signer(&db)?);
+    let throttle = web::Data::new(Throttle::new(config.throttle_limits()));
+    let proxies = web::Data::new(config.trusted_proxies()?);
+
+    let bind = (config.bind.clone(), config.port);
+    let workers = config.workers;
+    let max_body = config.max_body_bytes;
+    let db = web::Data::new(db);
+    let storage = web::Data::new(storage);
+    let config = web::Data::new(config);
+    let mut server = HttpServer::new(move || {
         App::new()
+            .app_data(db.clone())
+            .app_data(config.clone())
+            .app_data(storage.clone())
+            .app_data(signer.clone())
+            .app_data(throttle.clone())
+            .app_data(proxies.clone())
+            .app_data(web::JsonConfig::default().limit(max_body))
+            .app_data(web::PayloadConfig::default().limit(max_body))
             .wrap(Logger::default())
-            .service(hello)
-            // Customer services
-            .service(get_public_share)
-            .service(download_file)
-            // Admin service
-            .service(get_shares)
-            .service(create_file)
-            .service(delete_file)
-            .service(create_share)
-            .service(delete_share)
-    })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+            .configure(routes)
+    });
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+    server.bind(bind)?.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::Request;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::{header::RETRY_AFTER, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use file_serve::policy::{LockoutPolicy, PathPolicy};
+    use serde_json::{json, Value};
+
+    const ADMIN: (&str, &str) = ("admin", "hunter2");
+
+    /// What `test::init_service` gives back
+    trait TestApp: Service<Request, Response = ServiceResponse, Error = actix_web::Error> {}
+    impl<S> TestApp for S where S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>
+    {}
+
+    /// The app as `main` builds it, on an in-memory DB that can share the
+    /// temp dir and locks out after one wrong password
+    async fn app(td: &tempfile::TempDir) -> impl TestApp {
+        let db = Db::new_in_memory()
+            .unwrap()
+            .with_path_policy(PathPolicy::new(&[td.path()], &[]).unwrap())
+            .with_lockout_policy(LockoutPolicy {
+                free_attempts: 1,
+                ..LockoutPolicy::default()
+            });
+        db.create_admin(ADMIN.0, ADMIN.1).unwrap();
+        let config = Config::default();
+        let storage = Storage::new(&td.path().join("uploads"), 1024).unwrap();
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new(load_signer(&db).unwrap()))
+                .app_data(web::Data::new(Throttle::new(config.throttle_limits())))
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(storage))
+                .app_data(web::Data::new(config))
+                .configure(routes),
+        )
+        .await
+    }
+
+    fn peer() -> std::net::SocketAddr {
+        "203.0.113.7:4000".parse().unwrap()
+    }
+
+    async fn login(app: &impl TestApp) -> Cookie<'static> {
+        let res = test::call_service(
+            app,
+            TestRequest::post()
+                .uri("/admin/login")
+                .set_json(json!({ "username": ADMIN.0, "password": ADMIN.1 }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        cookie(&res, SESSION_COOKIE)
+    }
+
+    fn cookie(res: &ServiceResponse, name: &str) -> Cookie<'static> {
+        res.response()
+            .cookies()
+            .find(|c| c.name() == name)
+            .unwrap_or_else(|| panic!("no {name} cookie"))
+            .into_owned()
+    }
+
+    /// Shares `body` as admin
+    async fn share(app: &impl TestApp, admin: &Cookie<'static>, body: Value) -> ServiceResponse {
+        test::call_service(
+            app,
+            TestRequest::post()
+                .uri("/admin/share")
+                .cookie(admin.clone())
+                .set_json(body)
+                .to_request(),
+        )
+        .await
+    }
+
+    async fn slug_of(res: ServiceResponse) -> String {
+        assert_eq!(res.status(), StatusCode::OK);
+        let created: Value = test::read_body_json(res).await;
+        created["slug"].as_str().unwrap().to_string()
+    }
+
+    async fn error_of(res: ServiceResponse) -> String {
+        let body: Value = test::read_body_json(res).await;
+        body["error"].as_str().unwrap().to_string()
+    }
+
+    fn temp_file(td: &tempfile::TempDir) -> String {
+        let path = td.path().join("report.txt");
+        std::fs::write(&path, b"quarterly numbers").unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_need_a_session() {
+        let td = tempfile::tempdir().unwrap();
+        let app = app(&td).await;
+
+        // Turned away by `require_admin`, before any handler
+        let err =
+            test::try_call_service(&app, TestRequest::get().uri("/admin/shares").to_request())
+                .await
+                .err()
+                .unwrap();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(matches!(err.as_error(), Some(ServiceError::Unauthorized)));
+
+        let admin = login(&app).await;
+        let res = test::call_service(
+            &app,
+            TestRequest::get()
+                .uri("/admin/shares")
+                .cookie(admin)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn paths_outside_the_roots_are_forbidden() {
+        let td = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let app = app(&td).await;
+        let admin = login(&app).await;
+
+        let res = share(&app, &admin, json!({ "abs_path": temp_file(&outside) })).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_of(res).await, "path_rejected");
+    }
+
+    #[actix_web::test]
+    async fn used_up_shares_are_gone() {
+        let td = tempfile::tempdir().unwrap();
+        let app = app(&td).await;
+        let admin = login(&app).await;
+        let slug = slug_of(
+            share(
+                &app,
+                &admin,
+                json!({ "abs_path": temp_file(&td), "max_downloads": 1 }),
+            )
+            .await,
+        )
+        .await;
+        let download = || {
+            TestRequest::get()
+                .uri(&format!("/api/download/{slug}"))
+                .peer_addr(peer())
+                .to_request()
+        };
+
+        let res = test::call_service(&app, download()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "quarterly numbers".as_bytes());
+
+        let res = test::call_service(&app, download()).await;
+        assert_eq!(res.status(), StatusCode::GONE);
+        assert_eq!(error_of(res).await, "limit_reached");
+    }
+
+    #[actix_web::test]
+    async fn wrong_passwords_are_locked_out() {
+        let td = tempfile::tempdir().unwrap();
+        let app = app(&td).await;
+        let admin = login(&app).await;
+        let slug = slug_of(
+            share(
+                &app,
+                &admin,
+                json!({ "abs_path": temp_file(&td), "password": "open sesame" }),
+            )
+            .await,
+        )
+        .await;
+        let unlock = |password: &str| {
+            TestRequest::post()
+                .uri(&format!("/api/share/{slug}/unlock"))
+                .peer_addr(peer())
+                .set_json(json!({ "password": password }))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, unlock("guess")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(&app, unlock("guess again")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = res
+            .headers()
+            .get(RETRY_AFTER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0);
+        // Even the right password waits out the lockout
+        let res = test::call_service(&app, unlock("open sesame")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn unlock_cookie_opens_the_download() {
+        let td = tempfile::tempdir().unwrap();
+        let app = app(&td).await;
+        let admin = login(&app).await;
+        let slug = slug_of(
+            share(
+                &app,
+                &admin,
+                json!({ "abs_path": temp_file(&td), "password": "open sesame" }),
+            )
+            .await,
+        )
+        .await;
+        let download = || {
+            TestRequest::get()
+                .uri(&format!("/api/download/{slug}"))
+                .peer_addr(peer())
+        };
+
+        let res = test::call_service(&app, download().to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::call_service(
+            &app,
+            TestRequest::post()
+                .uri(&format!("/api/share/{slug}/unlock"))
+                .peer_addr(peer())
+                .set_json(json!({ "password": "open sesame" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let unlocked = cookie(&res, UNLOCK_COOKIE);
+        assert!(unlocked.http_only().unwrap_or(false));
+
+        let res = test::call_service(&app, download().cookie(unlocked).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "quarterly numbers".as_bytes());
+    }
 }
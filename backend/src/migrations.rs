@@ -0,0 +1,81 @@
+// src/migrations.rs
+use rusqlite::{Connection, TransactionBehavior};
+
+use crate::error::ServiceError;
+
+/// Schema changes in order. Entry `n` takes the DB from version `n` to `n + 1`,
+/// the version being stored in `PRAGMA user_version`.
+/// Only ever append here, never edit a migration that has shipped.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_init.sql"),
+    include_str!("../migrations/0002_admin.sql"),
+    include_str!("../migrations/0003_download_sessions.sql"),
+    include_str!("../migrations/0004_file_digest.sql"),
+    include_str!("../migrations/0005_upload_sessions.sql"),
+    include_str!("../migrations/0006_directory_shares.sql"),
+    include_str!("../migrations/0007_share_files.sql"),
+    include_str!("../migrations/0008_password_failures.sql"),
+    include_str!("../migrations/0009_share_unlock.sql"),
+    include_str!("../migrations/0010_signing_keys.sql"),
+    include_str!("../migrations/0011_share_throttle.sql"),
+    include_str!("../migrations/0012_share_archive.sql"),
+    include_str!("../migrations/0013_download_events.sql"),
+    include_str!("../migrations/0014_admin_audit.sql"),
+    include_str!("../migrations/0015_share_slug_nocase.sql"),
+];
+
+/// Schema version this binary expects
+pub const LATEST_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// # Errors
+///
+/// Fails only with generic db failure to read
+pub fn version(con: &Connection) -> Result<i64, ServiceError> {
+    Ok(con.pragma_query_value(None, "user_version", |r| r.get(0))?)
+}
+
+/// Brings the DB up to `LATEST_VERSION`, returns the version it started at
+///
+/// # Errors
+///
+/// `SchemaTooNew` if the DB was migrated by a newer binary,
+/// otherwise failure of a migration (which is rolled back)
+pub fn migrate(con: &mut Connection) -> Result<i64, ServiceError> {
+    migrate_to(con, LATEST_VERSION)
+}
+
+/// Applies migrations up to `target`, one transaction each.
+/// Split out from `migrate` so tests can build old schemas.
+///
+/// # Errors
+///
+/// Same as `migrate`
+pub fn migrate_to(con: &mut Connection, target: i64) -> Result<i64, ServiceError> {
+    let start = version(con)?;
+    if start > LATEST_VERSION {
+        return Err(ServiceError::SchemaTooNew {
+            found: start,
+            supported: LATEST_VERSION,
+        });
+    }
+
+    for (idx, sql) in MIGRATIONS.iter().enumerate() {
+        let next = idx as i64 + 1;
+        if next > target {
+            break;
+        }
+
+        // IMMEDIATE takes the write lock up front, so two processes starting
+        // together can't both apply the same step
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if version(&tx)? >= next {
+            continue;
+        }
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", next)?;
+        tx.commit()?;
+        log::info!("migrated database to schema v{next}");
+    }
+
+    Ok(start)
+}
@@ -0,0 +1,135 @@
+// src/policy.rs
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which paths on the host are allowed to be registered and shared.
+///
+/// A path is allowed if, after canonicalization (so symlinks are already
+/// resolved), it sits under one of `roots` and neither the path relative to
+/// that root nor any of its components matches a deny glob.
+/// No roots allows nothing, which is what `Db::open` and `Db::new_in_memory`
+/// start with until `Db::with_path_policy` gives them roots.
+#[derive(Debug, Clone, Default)]
+pub struct PathPolicy {
+    roots: Vec<PathBuf>,
+    deny: Option<GlobSet>,
+}
+
+impl PathPolicy {
+    /// Roots are canonicalized here, so a symlinked root is compared by its target
+    ///
+    /// # Errors
+    ///
+    /// Will error if a root doesn't exist or a deny glob is invalid
+    pub fn new<P: AsRef<Path>>(roots: &[P], deny: &[String]) -> io::Result<Self> {
+        let roots = roots
+            .iter()
+            .map(|r| std::fs::canonicalize(r.as_ref()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let deny_set = if deny.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in deny {
+                let glob = Glob::new(pattern)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                builder.add(glob);
+            }
+            Some(
+                builder
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            )
+        };
+
+        Ok(Self {
+            roots,
+            deny: deny_set,
+        })
+    }
+
+    #[must_use]
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// `canonical` must already be canonicalized
+    #[must_use]
+    pub fn allows(&self, canonical: &Path) -> bool {
+        let Some(rel) = self
+            .roots
+            .iter()
+            .find_map(|root| canonical.strip_prefix(root).ok())
+        else {
+            return false;
+        };
+
+        match &self.deny {
+            None => true,
+            Some(deny) => {
+                !deny.is_match(rel) && !rel.components().any(|c| deny.is_match(c.as_os_str()))
+            }
+        }
+    }
+}
+
+/// How long failed share passwords lock out a slug or client IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockoutPolicy {
+    /// Failures allowed before the first lockout
+    pub free_attempts: u32,
+    /// First lockout, doubled with every further failure
+    pub base_secs: u64,
+    /// Longest lockout
+    pub max_secs: u64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 5,
+            base_secs: 30,
+            max_secs: 3600,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Lockout after the `failures`th failure in a row, `None` while
+    /// still within the free attempts
+    #[must_use]
+    pub fn lockout_secs(&self, failures: u32) -> Option<u64> {
+        let doublings = failures.checked_sub(self.free_attempts)?;
+        let secs = self
+            .base_secs
+            .checked_shl(doublings)
+            .filter(|s| s >> doublings == self.base_secs)
+            .unwrap_or(u64::MAX);
+        Some(secs.min(self.max_secs))
+    }
+}
+
+/// What to do when a registered file's size or mtime no longer matches disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChangePolicy {
+    /// Record the new size / mtime and keep serving
+    #[default]
+    Refresh,
+    /// Refuse downloads until the file is registered again
+    Refuse,
+}
+
+/// What the janitor does with shares that expired or used up their downloads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiredSharePolicy {
+    /// Keep a record in `share_archive`, then delete the share
+    #[default]
+    Archive,
+    /// Delete the share without a trace
+    Delete,
+}
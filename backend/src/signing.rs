@@ -0,0 +1,124 @@
+// src/signing.rs
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ServiceError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Longest lifetime of a signed URL
+pub const MAX_SIGNED_URL_SECS: u64 = 30 * 24 * 3600;
+
+/// Key signed URLs are made with, see `Db::rotate_signing_key`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub id: i64,
+    #[serde(skip)]
+    pub secret: Vec<u8>,
+    pub created_at: String,
+}
+
+/// Query parameters that make a download URL signed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UrlSignature {
+    /// Unix seconds after which the URL stops working
+    pub expires: i64,
+    /// Id of the `SigningKey` used
+    pub kid: i64,
+    /// Base64url HMAC-SHA256 of `slug` and `expires`
+    pub sig: String,
+}
+
+impl UrlSignature {
+    /// Parameters as a query string, without the leading `?`
+    #[must_use]
+    pub fn to_query(&self) -> String {
+        format!("expires={}&kid={}&sig={}", self.expires, self.kid, self.sig)
+    }
+}
+
+/// In-memory copy of the signing keys, so signatures are checked without
+/// touching the DB. Cheap to clone, clones share the keys; refresh it with
+/// `set_keys` after changing them in the DB.
+#[derive(Debug, Clone, Default)]
+pub struct UrlSigner {
+    keys: Arc<RwLock<Vec<SigningKey>>>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
+}
+
+fn mac(secret: &[u8], slug: &str, expires: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    // Slugs can't hold a newline, so the message is unambiguous
+    mac.update(format!("{slug}\n{expires}").as_bytes());
+    mac
+}
+
+impl UrlSigner {
+    #[must_use]
+    pub fn new(keys: Vec<SigningKey>) -> Self {
+        let signer = Self::default();
+        signer.set_keys(keys);
+        signer
+    }
+
+    pub fn set_keys(&self, keys: Vec<SigningKey>) {
+        *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+    }
+
+    /// Signs `slug` for `valid_secs` from now with the newest key
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for a lifetime over `MAX_SIGNED_URL_SECS` or without keys
+    pub fn sign(&self, slug: &str, valid_secs: u64) -> Result<UrlSignature, ServiceError> {
+        if valid_secs == 0 || valid_secs > MAX_SIGNED_URL_SECS {
+            return Err(ServiceError::Invalid(format!(
+                "signed URLs last between 1 and {MAX_SIGNED_URL_SECS} seconds"
+            )));
+        }
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        let key = keys
+            .iter()
+            .max_by_key(|k| k.id)
+            .ok_or_else(|| ServiceError::Invalid("no signing key configured".into()))?;
+
+        let expires = now().saturating_add(i64::try_from(valid_secs).unwrap_or(i64::MAX));
+        let sig = mac(&key.secret, slug, expires).finalize().into_bytes();
+        Ok(UrlSignature {
+            expires,
+            kid: key.id,
+            sig: URL_SAFE_NO_PAD.encode(sig),
+        })
+    }
+
+    /// Checks expiry and signature, in constant time for the latter
+    ///
+    /// # Errors
+    ///
+    /// `BadSignature` saying what's wrong
+    pub fn verify(&self, slug: &str, signature: &UrlSignature) -> Result<(), ServiceError> {
+        if signature.expires <= now() {
+            return Err(ServiceError::BadSignature("link has expired"));
+        }
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        let key = keys
+            .iter()
+            .find(|k| k.id == signature.kid)
+            .ok_or(ServiceError::BadSignature("unknown signing key"))?;
+        let sig = URL_SAFE_NO_PAD
+            .decode(&signature.sig)
+            .map_err(|_| ServiceError::BadSignature("malformed signature"))?;
+        mac(&key.secret, slug, signature.expires)
+            .verify_slice(&sig)
+            .map_err(|_| ServiceError::BadSignature("signature does not match"))
+    }
+}
@@ -0,0 +1,107 @@
+// src/slug.rs
+use rand::{distr::Alphanumeric, seq::IndexedRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ServiceError;
+
+/// Bounds of admin-picked slugs
+pub const MIN_CUSTOM_SLUG: usize = 3;
+pub const MAX_CUSTOM_SLUG: usize = 64;
+
+/// The frontend serves shares at `/{slug}`, these are its own pages.
+/// Compared case-insensitively.
+const RESERVED_SLUGS: &[&str] = &[
+    "admin", "api", "assets", "static", "index", "login", "logout", "health", "favicon",
+];
+
+/// No 0/o, 1/l or case, so a slug survives being read aloud
+const READABLE: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyz";
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "brisk", "calm", "clever", "cosy", "crisp", "curly", "daring",
+    "eager", "early", "fancy", "fast", "fierce", "fluffy", "fond", "free", "fresh", "gentle",
+    "giant", "glad", "golden", "grand", "happy", "hardy", "honest", "humble", "jolly", "keen",
+    "kind", "lively", "lucky", "merry", "mighty", "misty", "neat", "nimble", "noble", "plucky",
+    "polite", "proud", "quick", "quiet", "rapid", "rosy", "royal", "rustic", "shiny", "silent",
+    "silver", "smart", "snowy", "sunny", "swift", "tidy", "tiny", "vivid", "warm", "wild", "windy",
+    "wise", "witty", "young",
+];
+
+const NOUNS: &[&str] = &[
+    "badger", "bear", "beaver", "bison", "cactus", "camel", "cedar", "comet", "crane", "daisy",
+    "dolphin", "eagle", "falcon", "fern", "finch", "forest", "fox", "gecko", "glacier", "harbor",
+    "hawk", "heron", "island", "jaguar", "koala", "lake", "lemur", "lion", "lotus", "maple",
+    "meadow", "moose", "moth", "newt", "oak", "ocean", "orca", "otter", "owl", "panda", "pebble",
+    "pine", "planet", "puffin", "quail", "raven", "reef", "river", "robin", "salmon", "seal",
+    "sparrow", "spruce", "stone", "swan", "tiger", "tulip", "turtle", "valley", "walrus", "willow",
+    "wolf", "wren", "zebra",
+];
+
+/// How generated share slugs look, see `Db::with_slug_alphabet`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugAlphabet {
+    /// `a-z`, `A-Z` and `0-9`
+    #[default]
+    Alphanumeric,
+    /// Lowercase letters and digits without the look-alikes 0, o, 1 and l
+    Readable,
+    /// `adjective-noun-NN` like `brave-otter-42`, ignores the length
+    Words,
+}
+
+impl SlugAlphabet {
+    /// A random slug, `len` characters long unless the alphabet is `Words`
+    #[must_use]
+    pub fn generate(self, len: usize) -> String {
+        let mut rng = rand::rng();
+        match self {
+            Self::Alphanumeric => (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect(),
+            Self::Readable => (0..len)
+                .map(|_| char::from(READABLE[rng.random_range(0..READABLE.len())]))
+                .collect(),
+            Self::Words => {
+                let adjective = ADJECTIVES.choose(&mut rng).copied().unwrap_or("brave");
+                let noun = NOUNS.choose(&mut rng).copied().unwrap_or("otter");
+                format!("{adjective}-{noun}-{}", rng.random_range(10..100))
+            }
+        }
+    }
+}
+
+/// Checks a slug an admin picked: ASCII letters, digits, `-` and `_`,
+/// starting and ending with a letter or digit, not one of the frontend's
+/// own routes
+///
+/// # Errors
+///
+/// `Invalid` naming what is wrong with the slug
+pub fn validate_custom(slug: &str) -> Result<(), ServiceError> {
+    let invalid = |why: String| Err(ServiceError::Invalid(format!("slug {slug:?} {why}")));
+
+    if !(MIN_CUSTOM_SLUG..=MAX_CUSTOM_SLUG).contains(&slug.len()) {
+        return invalid(format!(
+            "must be {MIN_CUSTOM_SLUG} to {MAX_CUSTOM_SLUG} characters long"
+        ));
+    }
+    if let Some(c) = slug
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return invalid(format!(
+            "contains {c:?}, only letters, digits, '-' and '_' are allowed"
+        ));
+    }
+    let edge = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !edge(slug.chars().next()) || !edge(slug.chars().last()) {
+        return invalid("must start and end with a letter or digit".into());
+    }
+    if RESERVED_SLUGS.iter().any(|r| r.eq_ignore_ascii_case(slug)) {
+        return invalid("is reserved".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,297 @@
+// src/storage.rs
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::error::ServiceError;
+
+/// Subdirectory of the storage dir holding uploads that aren't finished yet
+const PARTIAL_DIR: &str = ".partial";
+/// Prefix of partial files belonging to a chunked upload session
+const SESSION_PREFIX: &str = "session-";
+
+/// Managed directory uploaded files are written to.
+/// Finished uploads land in `<dir>/<id>/<name>` so names never clash.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Upload sessions currently being written to
+    busy: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Storage {
+    /// Creates the directory (and its partial dir) if needed
+    ///
+    /// # Errors
+    ///
+    /// Will error if the directory can't be created or resolved
+    pub fn new(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir.join(PARTIAL_DIR))?;
+        Ok(Self {
+            dir: dir.canonicalize()?,
+            max_bytes,
+            busy: Arc::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Largest accepted upload, in bytes
+    #[must_use]
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Starts a new upload in the partial dir
+    ///
+    /// # Errors
+    ///
+    /// Will error if the temp file can't be created
+    pub fn begin(&self) -> io::Result<PartialUpload> {
+        let path = self
+            .dir
+            .join(PARTIAL_DIR)
+            .join(uuid::Uuid::new_v4().simple().to_string());
+        let file = File::create_new(&path)?;
+        Ok(PartialUpload {
+            file: Some(file),
+            path,
+            written: 0,
+            limit: self.max_bytes,
+            keep: false,
+            _claim: None,
+        })
+    }
+
+    fn session_path(&self, id: &str) -> PathBuf {
+        self.dir
+            .join(PARTIAL_DIR)
+            .join(format!("{SESSION_PREFIX}{id}"))
+    }
+
+    /// Creates the empty partial file of a chunked upload session
+    ///
+    /// # Errors
+    ///
+    /// Will error if the file can't be created
+    pub fn create_session(&self, id: &str) -> io::Result<()> {
+        File::create_new(self.session_path(id)).map(drop)
+    }
+
+    /// Reopens a session's partial file to continue at `offset`, dropping
+    /// anything past it that was written but never recorded. Unlike `begin`
+    /// the file is kept when the returned upload is dropped.
+    ///
+    /// # Errors
+    ///
+    /// `UploadConflict` if another request is writing to the session or the
+    /// file is shorter than `offset`, otherwise IO failure
+    pub fn resume_session(
+        &self,
+        id: &str,
+        offset: u64,
+        size: u64,
+    ) -> Result<PartialUpload, ServiceError> {
+        let claim = Claim::take(&self.busy, id)
+            .ok_or_else(|| ServiceError::UploadConflict("upload is busy".into()))?;
+
+        let path = self.session_path(id);
+        let mut file = fs::OpenOptions::new().write(true).open(&path)?;
+        if file.metadata()?.len() < offset {
+            return Err(ServiceError::UploadConflict(
+                "partial file is shorter than recorded".into(),
+            ));
+        }
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(PartialUpload {
+            file: Some(file),
+            path,
+            written: offset,
+            limit: size,
+            keep: true,
+            _claim: Some(claim),
+        })
+    }
+
+    /// Removes a session's partial file, if any
+    pub fn remove_session(&self, id: &str) {
+        match fs::remove_file(self.session_path(id)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("could not remove partial upload {id}: {e}"),
+        }
+    }
+
+    /// Removes partial files that don't belong to one of `live_sessions`,
+    /// left over from a crash or expired sessions. Only safe while no
+    /// one-shot upload is running, e.g. at startup.
+    ///
+    /// # Errors
+    ///
+    /// Will error if the partial dir can't be listed
+    pub fn sweep_partials(&self, live_sessions: &[String]) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(self.dir.join(PARTIAL_DIR))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let live = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(SESSION_PREFIX))
+                .is_some_and(|id| live_sessions.iter().any(|l| l == id));
+            if !live {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Moves a finished upload into place. The rename stays on one
+    /// filesystem so the file appears complete or not at all.
+    ///
+    /// # Errors
+    ///
+    /// `Invalid` for an unusable name, otherwise IO failure
+    pub fn persist(&self, mut upload: PartialUpload, name: &str) -> Result<PathBuf, ServiceError> {
+        let name = sanitize_name(name)
+            .ok_or_else(|| ServiceError::Invalid(format!("unusable file name {name:?}")))?;
+
+        if let Some(file) = upload.file.take() {
+            file.sync_all()?;
+        }
+        let dir = self.dir.join(uuid::Uuid::new_v4().simple().to_string());
+        fs::create_dir(&dir)?;
+        let dest = dir.join(name);
+        fs::rename(&upload.path, &dest)?;
+        // Nothing left for Drop to clean up
+        upload.keep = true;
+        Ok(dest)
+    }
+
+    /// Removes a persisted upload, e.g. one whose registration failed.
+    /// Paths outside the storage dir are left alone, returns whether
+    /// anything was removed.
+    pub fn discard(&self, stored: &Path) -> bool {
+        let Some(dir) = stored.parent().filter(|d| d.parent() == Some(&self.dir)) else {
+            return false;
+        };
+        match fs::remove_dir_all(dir) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => {
+                log::warn!("could not remove upload {}: {e}", stored.display());
+                false
+            }
+        }
+    }
+}
+
+/// Marks an upload session busy until dropped
+#[derive(Debug)]
+struct Claim {
+    busy: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl Claim {
+    fn take(busy: &Arc<Mutex<HashSet<String>>>, id: &str) -> Option<Self> {
+        let mut set = busy
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        set.insert(id.to_string()).then(|| Self {
+            busy: Arc::clone(busy),
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.busy
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+/// File being uploaded. Dropping a one-shot upload without
+/// `Storage::persist` removes it, session uploads are kept.
+#[derive(Debug)]
+pub struct PartialUpload {
+    file: Option<File>,
+    path: PathBuf,
+    written: u64,
+    limit: u64,
+    keep: bool,
+    /// Held for its Drop
+    _claim: Option<Claim>,
+}
+
+impl PartialUpload {
+    /// Appends a chunk, refusing to grow past the size cap
+    ///
+    /// # Errors
+    ///
+    /// `TooLarge` past the cap, otherwise IO failure
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), ServiceError> {
+        let written = self.written + chunk.len() as u64;
+        if written > self.limit {
+            return Err(ServiceError::TooLarge { limit: self.limit });
+        }
+        match &mut self.file {
+            Some(file) => file.write_all(chunk)?,
+            None => return Err(io::Error::other("upload already finished").into()),
+        }
+        self.written = written;
+        Ok(())
+    }
+
+    /// Flushes written bytes to disk, so a recorded offset survives a crash
+    ///
+    /// # Errors
+    ///
+    /// Will error if the sync fails
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.as_ref().map_or(Ok(()), File::sync_data)
+    }
+
+    /// Bytes written so far
+    #[must_use]
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!(
+                "could not remove partial upload {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Keeps only the last component of a client supplied name, `None` if
+/// nothing usable is left
+#[must_use]
+pub fn sanitize_name(name: &str) -> Option<String> {
+    // Browsers on Windows may send the full path
+    let base = name.rsplit(['/', '\\']).next()?.trim();
+    if base.is_empty() || base == "." || base == ".." || base.chars().any(char::is_control) {
+        return None;
+    }
+    Some(base.to_string())
+}
@@ -0,0 +1,355 @@
+// src/throttle.rs
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    rt::time::{sleep, Sleep},
+    web::{self, Bytes},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::db::{Db, Share};
+use crate::download::client_ip;
+use crate::error::ServiceError;
+
+/// Request buckets kept before idle ones are dropped
+const MAX_TRACKED_IPS: usize = 10_000;
+/// Retry-After sent when a concurrency limit is hit
+const BUSY_RETRY_SECS: u64 = 5;
+
+/// Download limits, `None` means unlimited. Share rows can override
+/// `per_share` and `share_bytes_per_sec`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThrottleLimits {
+    /// Download requests per minute from one IP
+    pub requests_per_minute: Option<u32>,
+    /// Downloads running at once from one IP
+    pub per_ip: Option<u32>,
+    /// Downloads of one share running at once
+    pub per_share: Option<u32>,
+    /// Bandwidth of all downloads together
+    pub bytes_per_sec: Option<u64>,
+    /// Bandwidth of all downloads of one share together
+    pub share_bytes_per_sec: Option<u64>,
+}
+
+/// Token bucket refilling at `rate` per second up to `capacity`. Takes can
+/// overdraw it, the debt is what the caller has to wait out.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Takes `n` tokens, returns how long until the bucket is out of debt
+    fn take(&mut self, n: f64) -> Duration {
+        self.refill();
+        self.tokens -= n;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Takes one token only if available, otherwise returns the wait
+    fn try_take_one(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+type SharedBucket = Arc<Mutex<Bucket>>;
+
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// One second worth of bytes, so a chunk never waits much longer than
+/// its own transfer time
+fn byte_bucket(bytes_per_sec: u64) -> SharedBucket {
+    #[allow(clippy::cast_precision_loss)]
+    let rate = bytes_per_sec as f64;
+    Arc::new(Mutex::new(Bucket::new(rate, rate)))
+}
+
+#[derive(Debug, Default)]
+struct ShareState {
+    active: u32,
+    bandwidth: Option<SharedBucket>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requests: HashMap<String, Bucket>,
+    per_ip: HashMap<String, u32>,
+    per_share: HashMap<String, ShareState>,
+}
+
+/// Download limits shared by all workers. Cheap to clone, clones share
+/// their counters.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    limits: ThrottleLimits,
+    state: Arc<Mutex<State>>,
+    bandwidth: Option<SharedBucket>,
+}
+
+impl Throttle {
+    #[must_use]
+    pub fn new(limits: ThrottleLimits) -> Self {
+        Self {
+            limits,
+            state: Arc::default(),
+            bandwidth: limits.bytes_per_sec.map(byte_bucket),
+        }
+    }
+
+    /// Counts a request from `ip` against its rate
+    ///
+    /// # Errors
+    ///
+    /// `RateLimited` once the IP used up its requests per minute
+    pub fn check_rate(&self, ip: &str) -> Result<(), ServiceError> {
+        let Some(per_minute) = self.limits.requests_per_minute else {
+            return Ok(());
+        };
+        let mut state = lock(&self.state);
+        if state.requests.len() >= MAX_TRACKED_IPS {
+            state.requests.retain(|_, bucket| !bucket.is_full());
+        }
+        let per_minute = f64::from(per_minute);
+        state
+            .requests
+            .entry(ip.to_string())
+            .or_insert_with(|| Bucket::new(per_minute / 60.0, per_minute))
+            .try_take_one()
+            .map_err(|wait| ServiceError::RateLimited {
+                reason: "too many requests",
+                retry_after: wait.as_secs().max(1),
+            })
+    }
+
+    /// Takes a download slot for `ip` and `share`, held until the permit
+    /// is dropped. The share's own limits win over the server's.
+    ///
+    /// # Errors
+    ///
+    /// `RateLimited` if the IP or share already runs its limit of downloads
+    pub fn acquire(&self, ip: Option<&str>, share: &Share) -> Result<Permit, ServiceError> {
+        let per_share = share
+            .max_concurrent
+            .and_then(|m| u32::try_from(m).ok())
+            .or(self.limits.per_share);
+        let share_bandwidth = share
+            .bytes_per_sec
+            .and_then(|b| u64::try_from(b).ok())
+            .or(self.limits.share_bytes_per_sec);
+        let busy = |reason| ServiceError::RateLimited {
+            reason,
+            retry_after: BUSY_RETRY_SECS,
+        };
+
+        let mut state = lock(&self.state);
+        let ip_active = ip.map_or(0, |ip| state.per_ip.get(ip).copied().unwrap_or(0));
+        if self.limits.per_ip.is_some_and(|max| ip_active >= max) {
+            return Err(busy("too many downloads from this address"));
+        }
+        let share_state = state.per_share.entry(share.slug.clone()).or_default();
+        if per_share.is_some_and(|max| share_state.active >= max) {
+            return Err(busy("too many downloads of this share"));
+        }
+
+        share_state.active += 1;
+        if share_state.bandwidth.is_none() {
+            share_state.bandwidth = share_bandwidth.map(byte_bucket);
+        }
+        let share_bucket = share_state.bandwidth.clone();
+        if let Some(ip) = ip {
+            *state.per_ip.entry(ip.to_string()).or_default() += 1;
+        }
+
+        Ok(Permit {
+            throttle: self.clone(),
+            ip: ip.map(str::to_string),
+            slug: share.slug.clone(),
+            buckets: self.bandwidth.iter().cloned().chain(share_bucket).collect(),
+        })
+    }
+
+    fn release(&self, ip: Option<&str>, slug: &str) {
+        let mut state = lock(&self.state);
+        if let Some(ip) = ip {
+            if let Some(active) = state.per_ip.get_mut(ip) {
+                *active -= 1;
+                if *active == 0 {
+                    state.per_ip.remove(ip);
+                }
+            }
+        }
+        if let Some(share) = state.per_share.get_mut(slug) {
+            share.active -= 1;
+            // The bandwidth bucket goes too, picking up changed limits next time
+            if share.active == 0 {
+                state.per_share.remove(slug);
+            }
+        }
+    }
+}
+
+/// A running download's slot, see `Throttle::acquire`
+#[derive(Debug)]
+pub struct Permit {
+    throttle: Throttle,
+    ip: Option<String>,
+    slug: String,
+    /// Bandwidth caps the body has to respect
+    buckets: Vec<SharedBucket>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.throttle.release(self.ip.as_deref(), &self.slug);
+    }
+}
+
+/// Response body paced by the permit's bandwidth caps. Holds the permit,
+/// so the download counts as running until the body is done or dropped.
+pub struct Throttled {
+    body: BoxBody,
+    permit: Permit,
+    /// Chunk waiting for `delay` to pass
+    held: Option<Bytes>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttled {
+    #[must_use]
+    pub fn new(body: BoxBody, permit: Permit) -> Self {
+        Self {
+            body,
+            permit,
+            held: None,
+            delay: None,
+        }
+    }
+}
+
+impl MessageBody for Throttled {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(delay) = &mut this.delay {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+            return Poll::Ready(this.held.take().map(Ok));
+        }
+
+        let chunk = match Pin::new(&mut this.body).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            other => return other,
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let len = chunk.len() as f64;
+        let wait = this
+            .permit
+            .buckets
+            .iter()
+            .map(|bucket| lock(bucket).take(len))
+            .max()
+            .unwrap_or_default();
+        if wait.is_zero() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        let mut delay = Box::pin(sleep(wait));
+        if delay.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+        this.held = Some(chunk);
+        this.delay = Some(delay);
+        Poll::Pending
+    }
+}
+
+/// Middleware for the download routes: per-IP request rate, concurrent
+/// downloads per IP and per share, and bandwidth caps on the body.
+/// Passes requests through if the app has no `Throttle`.
+///
+/// # Errors
+///
+/// `RateLimited` when a limit is hit, otherwise errors of the DB lookup
+/// or the wrapped handler
+pub async fn throttle_downloads(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(throttle) = req.app_data::<web::Data<Throttle>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let ip = client_ip(req.request());
+    if let Some(ip) = &ip {
+        throttle.check_rate(ip)?;
+    }
+
+    let slug = req.match_info().get("slug").map(str::to_string);
+    let db = req.app_data::<web::Data<Db>>().cloned();
+    let share = match (slug, db) {
+        (Some(slug), Some(db)) => db.blocking(move |db| db.get_share(&slug)).await?,
+        _ => None,
+    };
+    // Unknown shares are the handler's 404
+    let Some(share) = share else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let permit = throttle.acquire(ip.as_deref(), &share)?;
+    let res = next.call(req).await?;
+    Ok(res.map_body(|_, body| Throttled::new(body.boxed(), permit).boxed()))
+}
@@ -0,0 +1,153 @@
+use file_serve::archive::{
+    dir_stats, list_dir, resolve, walk, write_archive, ArchiveFormat, MAX_COUNTED_ENTRIES,
+};
+use file_serve::policy::PathPolicy;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// shared/{a.txt, sub/b.txt, .secret, link-in -> a.txt, link-out -> ../outside.txt}
+fn tree() -> (tempfile::TempDir, PathBuf) {
+    let td = tempfile::tempdir().unwrap();
+    let base = td.path().canonicalize().unwrap();
+    let root = base.join("shared");
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+    std::fs::write(root.join("a.txt"), b"alpha").unwrap();
+    std::fs::write(root.join("sub/b.txt"), b"bravo!").unwrap();
+    std::fs::write(root.join(".secret"), b"hidden").unwrap();
+    std::fs::write(base.join("outside.txt"), b"not yours").unwrap();
+    std::os::unix::fs::symlink(root.join("a.txt"), root.join("link-in")).unwrap();
+    std::os::unix::fs::symlink(base.join("outside.txt"), root.join("link-out")).unwrap();
+    (td, root)
+}
+
+fn policy(root: &Path) -> PathPolicy {
+    PathPolicy::new(&[root], &[".*".to_string()]).unwrap()
+}
+
+#[test]
+fn walk_skips_denied_entries_and_outside_links() {
+    let (_td, root) = tree();
+    let policy = policy(&root);
+
+    let names: Vec<String> = walk(&root, &policy).map(|e| e.name).collect();
+    assert_eq!(names, ["a.txt", "link-in", "sub", "sub/b.txt"]);
+
+    let stats = dir_stats(&root, &policy).unwrap();
+    assert_eq!(stats.entries, 4);
+    assert_eq!(stats.total_bytes, 5 + 5 + 6);
+}
+
+#[test]
+fn dir_stats_gives_up_on_huge_trees() {
+    let td = tempfile::tempdir().unwrap();
+    let root = td.path().canonicalize().unwrap();
+    for i in 0..MAX_COUNTED_ENTRIES {
+        std::fs::File::create(root.join(i.to_string())).unwrap();
+    }
+    let policy = PathPolicy::new(&[&root], &[]).unwrap();
+    assert!(dir_stats(&root, &policy).is_some());
+
+    std::fs::File::create(root.join("one-more")).unwrap();
+    assert!(dir_stats(&root, &policy).is_none());
+    // Archiving still counts all of it
+    let stats = write_archive(ArchiveFormat::Zip, &root, &policy, std::io::sink()).unwrap();
+    assert_eq!(
+        stats.entries,
+        i64::try_from(MAX_COUNTED_ENTRIES).unwrap() + 1
+    );
+}
+
+#[test]
+fn zip_archive_reads_back() {
+    let (_td, root) = tree();
+    let mut out = Vec::new();
+    let stats = write_archive(ArchiveFormat::Zip, &root, &policy(&root), &mut out).unwrap();
+    assert_eq!(stats.entries, 4);
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(out)).unwrap();
+    assert_eq!(zip.len(), 4);
+    let mut body = String::new();
+    zip.by_name("shared/sub/b.txt")
+        .unwrap()
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "bravo!");
+    body.clear();
+    zip.by_name("shared/link-in")
+        .unwrap()
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "alpha");
+    assert!(zip.by_name("shared/sub/").unwrap().is_dir());
+    assert!(zip.by_name("shared/link-out").is_err());
+}
+
+#[test]
+fn tar_archive_reads_back() {
+    let (_td, root) = tree();
+    let mut out = Vec::new();
+    write_archive(ArchiveFormat::Tar, &root, &policy(&root), &mut out).unwrap();
+
+    let mut tar = tar::Archive::new(Cursor::new(out));
+    let mut seen = Vec::new();
+    for entry in tar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut body = String::new();
+        entry.read_to_string(&mut body).unwrap();
+        seen.push((name, body));
+    }
+    assert_eq!(
+        seen,
+        [
+            ("shared/a.txt".to_string(), "alpha".to_string()),
+            ("shared/link-in".to_string(), "alpha".to_string()),
+            ("shared/sub/".to_string(), String::new()),
+            ("shared/sub/b.txt".to_string(), "bravo!".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn resolve_stays_inside_the_walk() {
+    let (_td, root) = tree();
+    let policy = policy(&root);
+
+    let b = resolve(&root, "sub/b.txt", &policy).unwrap();
+    assert_eq!(b.name, "sub/b.txt");
+    assert_eq!(b.size, 6);
+    assert_eq!(
+        resolve(&root, "link-in", &policy).unwrap().source,
+        root.join("a.txt")
+    );
+    assert!(resolve(&root, "", &policy).unwrap().is_dir);
+
+    std::os::unix::fs::symlink(root.join("sub"), root.join("sub-link")).unwrap();
+    for bad in [
+        "../outside.txt",
+        "sub/../a.txt",
+        "sub//b.txt",
+        "./a.txt",
+        "link-out",
+        ".secret",
+        "sub-link/b.txt",
+        "a.txt/x",
+        "missing",
+    ] {
+        assert!(resolve(&root, bad, &policy).is_none(), "{bad}");
+    }
+
+    let names: Vec<String> = list_dir(&root, "", &policy)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(names, ["a.txt", "link-in", "sub"]);
+    let names: Vec<String> = list_dir(&root, "sub", &policy)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(names, ["sub/b.txt"]);
+    assert!(list_dir(&root, "a.txt", &policy).is_none());
+}
@@ -0,0 +1,21 @@
+use file_serve::auth::redact;
+use serde_json::json;
+
+#[test]
+fn redact_hides_secrets_at_any_depth() {
+    let params = json!({
+        "abs_path": "/data/payroll.xlsx",
+        "password": "hunter2",
+        "expires_at": null,
+        "nested": [{ "token": "abc", "new_password": null, "keep": 1 }],
+    });
+    assert_eq!(
+        redact(params),
+        json!({
+            "abs_path": "/data/payroll.xlsx",
+            "password": "[redacted]",
+            "expires_at": null,
+            "nested": [{ "token": "[redacted]", "new_password": null, "keep": 1 }],
+        })
+    );
+}
@@ -0,0 +1,84 @@
+use clap::Parser;
+use file_serve::config::{Cli, Command, Config, LogFormat};
+use file_serve::policy::ExpiredSharePolicy;
+use file_serve::slug::SlugAlphabet;
+use std::io::Write;
+
+fn write_config(body: &str) -> tempfile::NamedTempFile {
+    let mut f = tempfile::NamedTempFile::new().unwrap();
+    f.write_all(body.as_bytes()).unwrap();
+    f
+}
+
+#[test]
+fn cli_flags_override_file() {
+    let file = write_config(
+        r#"
+        port = 9000
+        db_path = "/var/lib/file-serve/data.db"
+        log_format = "json"
+        slug_alphabet = "words"
+        "#,
+    );
+    let path = file.path().to_str().unwrap();
+    let cli = Cli::try_parse_from(["file-serve", "--config", path, "--port", "9100"]).unwrap();
+
+    let config = Config::load(cli).unwrap();
+    assert_eq!(config.port, 9100);
+    assert_eq!(config.db_path.to_str(), Some("/var/lib/file-serve/data.db"));
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.slug_alphabet, SlugAlphabet::Words);
+    // Untouched keys keep their defaults
+    assert_eq!(config.slug_length, 8);
+}
+
+#[test]
+fn unknown_keys_and_bad_values_are_rejected() {
+    let typo = write_config("prot = 9000\n");
+    let cli = Cli::try_parse_from(["file-serve", "-c", typo.path().to_str().unwrap()]).unwrap();
+    assert!(Config::load(cli).is_err());
+
+    let cli = Cli::try_parse_from(["file-serve", "--slug-length", "2"]).unwrap();
+    assert!(Config::load(cli).is_err());
+
+    let cli = Cli::try_parse_from(["file-serve", "--lockout-secs", "7200"]).unwrap();
+    assert!(Config::load(cli).is_err());
+}
+
+#[test]
+fn janitor_subcommand_takes_the_same_flags() {
+    let cli = Cli::try_parse_from([
+        "file-serve",
+        "--on-share-expired",
+        "delete",
+        "--purge-orphan-uploads",
+        "true",
+        "janitor",
+    ])
+    .unwrap();
+    assert_eq!(cli.command, Some(Command::Janitor));
+
+    let config = Config::load(cli).unwrap();
+    assert_eq!(config.on_share_expired, ExpiredSharePolicy::Delete);
+    assert!(config.purge_orphan_uploads);
+    assert_eq!(Config::default().janitor_interval_secs, 3600);
+}
+
+#[test]
+fn share_url_joins_base_and_slug() {
+    let config = Config {
+        public_base_url: Some("https://files.example.com/".into()),
+        ..Config::default()
+    };
+    assert_eq!(
+        config.share_url("abc123").as_deref(),
+        Some("https://files.example.com/abc123")
+    );
+    assert_eq!(Config::default().share_url("abc123"), None);
+}
+
+#[test]
+fn example_config_parses() {
+    let config = Config::from_file("file-serve.example.toml".as_ref()).unwrap();
+    config.validate().unwrap();
+}
@@ -1,4 +1,12 @@
-use file_serve::db::{Db, Share}; // Adjust this path based on your actual crate structure
+use file_serve::db::{
+    AdminAuditQuery, CreateShareReq, Credential, Db, DownloadEventQuery, DownloadOutcome,
+    DownloadRequest, DownloadTarget, FileKind, HashState, LockoutScope, NewAdminAudit,
+    NewDownloadEvent, ShareKind, UpdateShareReq,
+};
+use file_serve::error::ServiceError;
+use file_serve::hashing;
+use file_serve::policy::{FileChangePolicy, LockoutPolicy, PathPolicy};
+use file_serve::slug::SlugAlphabet;
 use std::fs::File;
 use std::io::Write;
 
@@ -11,9 +19,45 @@ fn temp_file_with_size(bytes: usize) -> (tempfile::TempDir, String) {
     (dir, path.to_string_lossy().to_string())
 }
 
+/// Lets a test db share anything below the temp dir
+fn temp_roots() -> PathPolicy {
+    PathPolicy::new(&[std::env::temp_dir()], &[]).unwrap()
+}
+
+fn temp_db() -> Db {
+    Db::new_in_memory().unwrap().with_path_policy(temp_roots())
+}
+
+/// A request continuing the download of `session` past byte 0
+fn resume(session: &str) -> DownloadRequest<'_> {
+    DownloadRequest {
+        session: Some(session),
+        offset: 5,
+    }
+}
+
+/// The client of `target` only got `sent` bytes before it dropped
+fn interrupted(db: &Db, target: &DownloadTarget, sent: i64) {
+    db.return_download_bytes(&target.session, &target.abs_path, target.reserved - sent)
+        .unwrap();
+}
+
+fn share_req(abs_path: &str) -> CreateShareReq {
+    CreateShareReq {
+        abs_path: abs_path.to_string(),
+        password: None,
+        expires_at: None,
+        max_downloads: None,
+        abs_paths: Vec::new(),
+        slug: None,
+        max_concurrent: None,
+        bytes_per_sec: None,
+    }
+}
+
 #[test]
 fn file_create_or_get_is_idempotent() {
-    let db = Db::new().unwrap(); // or Db::new_in_memory() if you add that
+    let db = temp_db();
     let (_td, p) = temp_file_with_size(1234);
 
     let a = db.create_or_get_file(&p).unwrap();
@@ -24,23 +68,1234 @@ fn file_create_or_get_is_idempotent() {
 
 #[test]
 fn share_create_and_delete_works() {
-    let db = Db::new().unwrap();
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+
+    let share = db.create_share(&share_req(&p)).unwrap();
+
+    assert!(db.get_share(&share.slug).unwrap().is_some());
+    assert!(db.delete_share(&share.slug).unwrap());
+    assert!(db.get_share(&share.slug).unwrap().is_none());
+}
+
+#[test]
+fn download_stops_at_max_downloads() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(2),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    assert!(db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .is_ok());
+    assert!(db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .is_ok());
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", DownloadRequest::default()),
+        Err(ServiceError::LimitReached)
+    ));
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 2);
+}
+
+#[test]
+fn expired_share_is_not_served() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            expires_at: Some("2000-01-01T00:00:00Z".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", DownloadRequest::default()),
+        Err(ServiceError::Expired)
+    ));
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 0);
+}
+
+#[test]
+fn unknown_slug_is_not_found() {
+    let db = temp_db();
+    assert!(matches!(
+        db.get_download_target("no-such-slug", "", DownloadRequest::default()),
+        Err(ServiceError::NotFound)
+    ));
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let db = temp_db();
     let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    assert!(matches!(
+        db.get_download_target(&share.slug, "guess", DownloadRequest::default()),
+        Err(ServiceError::BadPassword)
+    ));
+    assert!(db
+        .get_download_target(&share.slug, "secret", DownloadRequest::default())
+        .is_ok());
+}
+
+#[test]
+fn unlock_token_stands_in_for_the_password() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let other = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    assert!(matches!(
+        db.unlock_share(&share.slug, "guess"),
+        Err(ServiceError::BadPassword)
+    ));
+    assert!(matches!(
+        db.get_download_target(&share.slug, Credential::None, DownloadRequest::default()),
+        Err(ServiceError::BadPassword)
+    ));
+
+    let unlock = db.unlock_share(&share.slug, "secret").unwrap();
+    let token = Credential::Token(&unlock.token);
+    let target = db
+        .get_download_target(&share.slug, token, DownloadRequest::default())
+        .unwrap();
+    // Only for the share it was issued for
+    assert!(matches!(
+        db.get_download_target(&other.slug, token, DownloadRequest::default()),
+        Err(ServiceError::BadPassword)
+    ));
+    // A download session it started can be continued without it,
+    // but a new download from byte 0 needs the credential again
+    interrupted(&db, &target, 5);
+    assert!(db
+        .get_download_target(&share.slug, Credential::None, resume(&target.session))
+        .is_ok());
+    let restart = DownloadRequest {
+        offset: 0,
+        ..resume(&target.session)
+    };
+    assert!(matches!(
+        db.get_download_target(&share.slug, Credential::None, restart),
+        Err(ServiceError::BadPassword)
+    ));
+    assert!(matches!(
+        db.get_download_target(&other.slug, Credential::None, resume(&target.session)),
+        Err(ServiceError::BadPassword)
+    ));
+}
+
+#[test]
+fn download_session_ends_with_its_credential() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let signed = Credential::SignedUrl {
+        expires: i64::try_from(now).unwrap() + 1,
+    };
+
+    let target = db
+        .get_download_target(&share.slug, signed, DownloadRequest::default())
+        .unwrap();
+    interrupted(&db, &target, 5);
+    assert!(db
+        .get_download_target(&share.slug, Credential::None, resume(&target.session))
+        .is_ok());
+    std::thread::sleep(std::time::Duration::from_millis(2100));
+    assert!(matches!(
+        db.get_download_target(&share.slug, Credential::None, resume(&target.session)),
+        Err(ServiceError::BadPassword)
+    ));
+}
+
+#[test]
+fn wrong_passwords_lock_out_slug_and_ip() {
+    let db = temp_db().with_lockout_policy(LockoutPolicy {
+        free_attempts: 2,
+        base_secs: 60,
+        max_secs: 600,
+    });
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let open = db.create_share(&share_req(&p)).unwrap();
+    let attempt = |slug: &str, ip: &str, password: &str| {
+        db.guard_password(slug, Some(ip), |db| {
+            db.get_download_target(slug, password, DownloadRequest::default())
+        })
+    };
+
+    for _ in 0..2 {
+        assert!(matches!(
+            attempt(&share.slug, "10.0.0.1", "guess"),
+            Err(ServiceError::BadPassword)
+        ));
+    }
+    // Locked for everyone now, even with the right password
+    for ip in ["10.0.0.1", "10.0.0.2"] {
+        match attempt(&share.slug, ip, "secret") {
+            Err(ServiceError::TooManyAttempts { retry_after }) => {
+                assert!((1..=60).contains(&retry_after));
+            }
+            other => panic!("expected a lockout, got {other:?}"),
+        }
+    }
+    // Shares without a password don't care
+    assert!(attempt(&open.slug, "10.0.0.1", "").is_ok());
+
+    let failures = db.list_password_failures().unwrap();
+    assert_eq!(failures.len(), 2);
+    assert!(failures
+        .iter()
+        .all(|f| f.failures == 2 && f.locked_until.is_some()));
+
+    // The IP stays locked out after its slug lockout is lifted
+    assert!(db
+        .clear_password_failures(LockoutScope::Slug, &share.slug)
+        .unwrap());
+    assert!(attempt(&share.slug, "10.0.0.2", "secret").is_ok());
+    assert!(matches!(
+        attempt(&share.slug, "10.0.0.1", "secret"),
+        Err(ServiceError::TooManyAttempts { .. })
+    ));
+    assert!(db
+        .clear_password_failures(LockoutScope::Ip, "10.0.0.1")
+        .unwrap());
+    assert!(attempt(&share.slug, "10.0.0.1", "secret").is_ok());
+}
+
+#[test]
+fn parallel_guesses_stay_within_the_free_attempts() {
+    let td = tempfile::tempdir().unwrap();
+    let db = Db::open(td.path().join("lockout.db"))
+        .unwrap()
+        .with_path_policy(temp_roots())
+        .with_lockout_policy(LockoutPolicy {
+            free_attempts: 2,
+            base_secs: 60,
+            max_secs: 600,
+        });
+    let (_files, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let db = db.clone();
+            let slug = share.slug.clone();
+            std::thread::spawn(move || {
+                let ip = format!("10.0.0.{i}");
+                db.guard_password(&slug, Some(&ip), |db| {
+                    db.get_download_target(&slug, "guess", DownloadRequest::default())
+                })
+            })
+        })
+        .collect();
+    let checked = handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .filter(|res| matches!(res, Err(ServiceError::BadPassword)))
+        .count();
+    assert_eq!(checked, 2);
+}
+
+#[test]
+fn right_password_keeps_the_slug_counter() {
+    let db = temp_db().with_lockout_policy(LockoutPolicy {
+        free_attempts: 2,
+        base_secs: 60,
+        max_secs: 600,
+    });
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let attempt = |ip: &str, password: &str| {
+        db.guard_password(&share.slug, Some(ip), |db| {
+            db.unlock_share(&share.slug, password)
+        })
+    };
+
+    assert!(attempt("10.0.0.1", "guess").is_err());
+    attempt("10.0.0.2", "secret").unwrap();
+    // The right password takes back only its own attempt
+    let failures = db.list_password_failures().unwrap();
+    assert_eq!(failures.len(), 2);
+    assert!(failures
+        .iter()
+        .all(|f| f.failures == 1 && f.locked_until.is_none() && f.key != "10.0.0.2"));
+    // So it doesn't give a guesser more attempts on the slug
+    assert!(attempt("10.0.0.3", "guess").is_err());
+    assert!(matches!(
+        attempt("10.0.0.4", "secret"),
+        Err(ServiceError::TooManyAttempts { .. })
+    ));
+}
+
+#[test]
+fn lockouts_double_up_to_the_cap() {
+    let policy = LockoutPolicy {
+        free_attempts: 3,
+        base_secs: 30,
+        max_secs: 100,
+    };
+    assert_eq!(policy.lockout_secs(2), None);
+    assert_eq!(policy.lockout_secs(3), Some(30));
+    assert_eq!(policy.lockout_secs(4), Some(60));
+    assert_eq!(policy.lockout_secs(5), Some(100));
+    assert_eq!(policy.lockout_secs(200), Some(100));
+}
+
+#[test]
+fn create_share_validates_limits() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+
+    for req in [
+        CreateShareReq {
+            expires_at: Some("next tuesday".to_string()),
+            ..share_req(&p)
+        },
+        CreateShareReq {
+            max_downloads: Some(-1),
+            ..share_req(&p)
+        },
+        CreateShareReq {
+            max_downloads: Some(0),
+            ..share_req(&p)
+        },
+        CreateShareReq {
+            max_concurrent: Some(0),
+            ..share_req(&p)
+        },
+        CreateShareReq {
+            bytes_per_sec: Some(-5),
+            ..share_req(&p)
+        },
+    ] {
+        assert!(matches!(
+            db.create_share(&req),
+            Err(ServiceError::Invalid(_))
+        ));
+    }
+
+    let share = db
+        .create_share(&CreateShareReq {
+            max_concurrent: Some(2),
+            bytes_per_sec: Some(65536),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let stored = db.get_share(&share.slug).unwrap().unwrap();
+    assert_eq!(stored.max_concurrent, Some(2));
+    assert_eq!(stored.bytes_per_sec, Some(65536));
+}
+
+#[test]
+fn admin_login_issues_session() {
+    let db = temp_db();
+    let username = format!("admin-{}", uuid::Uuid::new_v4());
+    db.create_admin(&username, "hunter2").unwrap();
+
+    assert!(db.login(&username, "wrong").unwrap().is_none());
+    assert!(db.login("nobody-here", "hunter2").unwrap().is_none());
+
+    let session = db.login(&username, "hunter2").unwrap().unwrap();
+    let admin = db.get_session_admin(&session.token).unwrap().unwrap();
+    assert_eq!(admin.username, username);
+
+    assert!(db.delete_session(&session.token).unwrap());
+    assert!(db.get_session_admin(&session.token).unwrap().is_none());
+}
+
+#[test]
+fn policy_rejects_paths_outside_roots() {
+    let (root, inside) = temp_file_with_size(10);
+    let (_other, outside) = temp_file_with_size(10);
+    let policy = PathPolicy::new(&[root.path()], &[]).unwrap();
+    let db = Db::new_in_memory().unwrap();
+    // Without roots nothing can be shared
+    assert!(matches!(
+        db.create_or_get_file(&inside),
+        Err(ServiceError::PathRejected(_))
+    ));
+    let db = db.with_path_policy(policy);
+
+    assert!(db.create_or_get_file(&inside).is_ok());
+    assert!(matches!(
+        db.create_or_get_file(&outside),
+        Err(ServiceError::PathRejected(_))
+    ));
+
+    // A symlink inside the root pointing out of it is judged by its target
+    let link = root.path().join("escape.bin");
+    std::os::unix::fs::symlink(&outside, &link).unwrap();
+    assert!(matches!(
+        db.create_or_get_file(&link.to_string_lossy()),
+        Err(ServiceError::PathRejected(_))
+    ));
+}
+
+#[test]
+fn policy_deny_globs_match_any_component() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir(root.path().join(".ssh")).unwrap();
+    let key = root.path().join(".ssh/id_rsa");
+    let pem = root.path().join("server.key");
+    File::create(&key).unwrap();
+    File::create(&pem).unwrap();
+
+    let policy = PathPolicy::new(&[root.path()], &[".*".to_string(), "*.key".to_string()]).unwrap();
+    let db = temp_db().with_path_policy(policy);
+
+    for p in [key, pem] {
+        assert!(matches!(
+            db.create_or_get_file(&p.to_string_lossy()),
+            Err(ServiceError::PathRejected(_))
+        ));
+    }
+}
+
+#[test]
+fn concurrent_downloads_respect_limit() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(3),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let db = db.clone();
+            let slug = share.slug.clone();
+            std::thread::spawn(move || {
+                db.get_download_target(&slug, "", DownloadRequest::default())
+                    .is_ok()
+            })
+        })
+        .collect();
+    let served = handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .filter(|ok| *ok)
+        .count();
+
+    assert_eq!(served, 3);
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 3);
+}
+
+#[test]
+fn open_persists_to_the_given_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("shares.db");
+    let (_td, p) = temp_file_with_size(10);
+
+    let slug = {
+        let db = Db::open(&db_path).unwrap().with_path_policy(temp_roots());
+        db.create_share(&share_req(&p)).unwrap().slug
+    };
+
+    let db = Db::open(&db_path).unwrap();
+    assert!(db.get_share(&slug).unwrap().is_some());
+}
+
+#[test]
+fn in_memory_dbs_are_isolated() {
+    let a = temp_db();
+    let b = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+
+    let share = a.create_share(&share_req(&p)).unwrap();
+    assert!(b.get_share(&share.slug).unwrap().is_none());
+}
+
+#[test]
+fn only_downloads_from_byte_zero_are_counted() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(1),
+            ..share_req(&p)
+        })
+        .unwrap();
+
+    let first = db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .unwrap();
+    assert!(first.counted);
+    assert_eq!(first.size_bytes, 10);
+    assert_eq!(first.reserved, 10);
+    assert!(first.mtime.is_some());
+
+    // Continuing with the session doesn't use up the limit
+    interrupted(&db, &first, 5);
+    let resumed = db
+        .get_download_target(&share.slug, "", resume(&first.session))
+        .unwrap();
+    assert!(!resumed.counted);
+    assert_eq!(resumed.session, first.session);
+    assert_eq!(resumed.reserved, 5);
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 1);
+
+    // Starting over is a new download, session or not
+    let again = DownloadRequest {
+        offset: 0,
+        ..resume(&first.session)
+    };
+    // So is continuing once the session was sent the whole file, or
+    // continuing someone else's download
+    for request in [again, resume(&first.session), resume("made-up")] {
+        assert!(matches!(
+            db.get_download_target(&share.slug, "", request),
+            Err(ServiceError::LimitReached)
+        ));
+    }
+}
+
+#[test]
+fn a_session_is_sent_each_file_once() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(1),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let first = db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .unwrap();
+    interrupted(&db, &first, 2);
+    let from = |offset| DownloadRequest {
+        session: Some(&first.session),
+        offset,
+    };
+
+    // Parallel resumes can't reserve more than the file
+    let resumed = db.get_download_target(&share.slug, "", from(2)).unwrap();
+    assert_eq!(resumed.reserved, 8);
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", from(2)),
+        Err(ServiceError::LimitReached)
+    ));
+    // What a resume didn't send can be resumed again
+    interrupted(&db, &resumed, 4);
+    let again = db.get_download_target(&share.slug, "", from(6)).unwrap();
+    assert!(!again.counted);
+    assert_eq!(again.reserved, 4);
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", from(6)),
+        Err(ServiceError::LimitReached)
+    ));
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 1);
+}
+
+#[test]
+fn missing_file_is_gone_without_counting() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db.create_share(&share_req(&p)).unwrap();
+    std::fs::remove_file(&p).unwrap();
+
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", DownloadRequest::default()),
+        Err(ServiceError::FileMissing)
+    ));
+    assert!(matches!(
+        db.get_public_share(&share.slug),
+        Err(ServiceError::FileMissing)
+    ));
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 0);
+}
+
+#[test]
+fn changed_file_follows_change_policy() {
+    let (_td, p) = temp_file_with_size(10);
+
+    let refresh = temp_db();
+    let refuse = temp_db().with_change_policy(FileChangePolicy::Refuse);
+    let refresh_share = refresh.create_share(&share_req(&p)).unwrap();
+    let refuse_share = refuse.create_share(&share_req(&p)).unwrap();
+
+    std::fs::write(&p, vec![1u8; 25]).unwrap();
+
+    let target = refresh
+        .get_download_target(&refresh_share.slug, "", DownloadRequest::default())
+        .unwrap();
+    assert_eq!(target.size_bytes, 25);
+    let public = refresh
+        .get_public_share(&refresh_share.slug)
+        .unwrap()
+        .unwrap();
+    assert_eq!(public.file_size, 25);
+
+    assert!(matches!(
+        refuse.get_download_target(&refuse_share.slug, "", DownloadRequest::default()),
+        Err(ServiceError::FileChanged)
+    ));
+}
+
+#[test]
+fn disk_validators_only_read() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db.create_share(&share_req(&p)).unwrap();
+    std::fs::write(&p, vec![1u8; 20]).unwrap();
+
+    let (size, mtime) = db.disk_validators(&share.slug, None).unwrap().unwrap();
+    assert_eq!(size, 20);
+    assert!(mtime.is_some());
+    // The change is left for the download to find once it's let in
+    let recorded = db.get_share_file(&share.slug).unwrap().unwrap();
+    assert_eq!(recorded.size_bytes, 10);
+    assert!(db
+        .disk_validators(&share.slug, Some("demo.bin"))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn registered_file_gets_sha256_and_verifies() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+
+    let file = db.create_or_get_file(&p).unwrap();
+    // sha256 of ten zero bytes
+    let expected = "01d448afd928065458cf670b60f5a594d735af0172c8d67f22a81680132681ca";
+    assert_eq!(file.sha256.as_deref(), Some(expected));
+    let share = db.create_share(&share_req(&p)).unwrap();
+    let public = db.get_public_share(&share.slug).unwrap().unwrap();
+    assert_eq!(public.sha256.as_deref(), Some(expected));
+
+    let ok = db.verify_file_digest(&file.id).unwrap();
+    assert_eq!(ok.matches, Some(true));
+
+    // Same size, different bytes: metadata can't tell, the digest can
+    let mtime = std::fs::metadata(&p).unwrap().modified().unwrap();
+    std::fs::write(&p, vec![1u8; 10]).unwrap();
+    File::options()
+        .write(true)
+        .open(&p)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    let bad = db.verify_file_digest(&file.id).unwrap();
+    assert_eq!(bad.matches, Some(false));
+    assert_eq!(bad.stored.as_deref(), Some(expected));
+
+    assert!(matches!(
+        db.verify_file_digest("no-such-id"),
+        Err(ServiceError::NotFound)
+    ));
+}
+
+#[test]
+fn big_files_are_hashed_in_the_background() {
+    let db = temp_db();
+    let size = usize::try_from(hashing::INLINE_HASH_LIMIT).unwrap() + 1;
+    let (_td, p) = temp_file_with_size(size);
+
     let file = db.create_or_get_file(&p).unwrap();
+    assert_eq!(file.sha256, None);
+    assert_eq!(file.hash_state, Some(HashState::Pending));
+
+    let mut hashed = None;
+    for _ in 0..100 {
+        let now = db.get_file(&file.id).unwrap().unwrap();
+        if now.sha256.is_some() {
+            hashed = Some(now);
+            break;
+        }
+        assert_eq!(now.hash_state, Some(HashState::Pending));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(hashed.expect("hash job never ran").hash_state, None);
+}
+
+#[test]
+fn upload_sessions_track_offset_and_go_stale() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("uploads.db");
+    let db = Db::open(&db_path).unwrap();
+
+    let session = db.create_upload_session("big.iso", 100).unwrap();
+    assert_eq!(session.received_bytes, 0);
+    let moved = db.set_upload_received(&session.id, 40).unwrap().unwrap();
+    assert_eq!(moved.received_bytes, 40);
+    assert_eq!(
+        db.get_upload_session(&session.id)
+            .unwrap()
+            .unwrap()
+            .received_bytes,
+        40
+    );
+    assert!(db.create_upload_session("neg.bin", -1).is_err());
+
+    rusqlite::Connection::open(&db_path)
+        .unwrap()
+        .execute(
+            "UPDATE upload_session SET expires_at = datetime('now', '-1 hour')",
+            [],
+        )
+        .unwrap();
+    assert!(db.get_upload_session(&session.id).unwrap().is_none());
+    assert_eq!(db.purge_stale_upload_sessions().unwrap(), vec![session.id]);
+    assert!(db.upload_session_ids().unwrap().is_empty());
+}
+
+#[test]
+fn directory_share_reports_totals() {
+    let db = temp_db();
+    let td = tempfile::tempdir().unwrap();
+    std::fs::create_dir(td.path().join("nested")).unwrap();
+    std::fs::write(td.path().join("one.bin"), vec![0u8; 100]).unwrap();
+    std::fs::write(td.path().join("nested/two.bin"), vec![0u8; 50]).unwrap();
+
+    let share = db
+        .create_share(&share_req(td.path().to_str().unwrap()))
+        .unwrap();
+    let public = db.get_public_share(&share.slug).unwrap().unwrap();
+    assert_eq!(public.kind, ShareKind::Dir);
+    assert_eq!(public.entry_count, Some(3));
+    assert_eq!(public.file_size, 150);
+    assert_eq!(public.sha256, None);
+
+    let target = db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .unwrap();
+    assert_eq!(target.kind, ShareKind::Dir);
+}
+
+#[test]
+fn directory_share_can_be_browsed() {
+    let db = temp_db();
+    let td = tempfile::tempdir().unwrap();
+    std::fs::create_dir(td.path().join("nested")).unwrap();
+    std::fs::write(td.path().join("one.bin"), vec![0u8; 100]).unwrap();
+    std::fs::write(td.path().join("nested/two.bin"), vec![0u8; 50]).unwrap();
 
     let share = db
-        .create_share(&Share {
-            slug: "".to_string(), // slug is generated internally
-            file_id: file.id.clone(),
-            expires_at: None,
-            max_downloads: None,
-            dl_count: 0,
-            password_hash: None,
-            created_at: "".to_string(), // ignored
+        .create_share(&CreateShareReq {
+            password: Some("hunter2".into()),
+            max_downloads: Some(1),
+            ..share_req(td.path().to_str().unwrap())
         })
         .unwrap();
+    assert!(matches!(
+        db.list_share_dir(&share.slug, "", "wrong"),
+        Err(ServiceError::BadPassword)
+    ));
 
-    assert!(db.get_share(&share.slug).unwrap().is_some());
-    assert!(db.delete_share(&share.slug).unwrap());
-    assert!(db.get_share(&share.slug).unwrap().is_none());
+    let root = db.list_share_dir(&share.slug, "", "hunter2").unwrap();
+    let paths: Vec<_> = root.entries.iter().map(|e| e.path.as_str()).collect();
+    assert_eq!(paths, ["nested", "one.bin"]);
+    assert_eq!(root.entries[0].kind, FileKind::Dir);
+    let nested = db
+        .list_share_dir(&share.slug, "nested/", "hunter2")
+        .unwrap();
+    assert_eq!(nested.path, "nested");
+    assert_eq!(nested.entries[0].name, "two.bin");
+    assert_eq!(nested.entries[0].size_bytes, 50);
+    assert!(matches!(
+        db.list_share_dir(&share.slug, "..", "hunter2"),
+        Err(ServiceError::NotFound)
+    ));
+
+    let two = db
+        .get_member_target(
+            &share.slug,
+            "nested/two.bin",
+            "hunter2",
+            DownloadRequest::default(),
+        )
+        .unwrap();
+    assert_eq!(two.file_name, "two.bin");
+    assert_eq!(two.size_bytes, 50);
+    assert_eq!(two.kind, ShareKind::File);
+    for bad in ["nested", "../one.bin", "nested/../one.bin"] {
+        assert!(matches!(
+            db.get_member_target(&share.slug, bad, "hunter2", resume(&two.session)),
+            Err(ServiceError::NotFound)
+        ));
+    }
+}
+
+#[test]
+fn directory_files_and_archive_count_once_each() {
+    let db = temp_db();
+    let td = tempfile::tempdir().unwrap();
+    std::fs::create_dir(td.path().join("sub")).unwrap();
+    std::fs::write(td.path().join("a.txt"), b"aaaa").unwrap();
+    std::fs::write(td.path().join("sub/b.txt"), b"bb").unwrap();
+    let share = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(2),
+            ..share_req(td.path().to_str().unwrap())
+        })
+        .unwrap();
+
+    let a = db
+        .get_member_target(&share.slug, "a.txt", "", DownloadRequest::default())
+        .unwrap();
+    let same_session = DownloadRequest {
+        offset: 0,
+        ..resume(&a.session)
+    };
+    let b = db
+        .get_member_target(&share.slug, "sub/b.txt", "", same_session)
+        .unwrap();
+    assert!(a.counted && !b.counted);
+
+    // Browsing file by file used one download, the archive is the second
+    let zip = db
+        .get_download_target(&share.slug, "", DownloadRequest::default())
+        .unwrap();
+    assert_eq!(zip.kind, ShareKind::Dir);
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 2);
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", DownloadRequest::default()),
+        Err(ServiceError::LimitReached)
+    ));
+}
+
+#[test]
+fn bundle_share_lists_and_serves_files() {
+    let db = temp_db();
+    let td = tempfile::tempdir().unwrap();
+    let a = td.path().join("a.txt");
+    let b = td.path().join("b.txt");
+    std::fs::write(&a, b"aaaa").unwrap();
+    std::fs::write(&b, b"bb").unwrap();
+    let paths = vec![
+        a.to_str().unwrap().to_string(),
+        b.to_str().unwrap().to_string(),
+    ];
+
+    let share = db
+        .create_share(&CreateShareReq {
+            abs_paths: paths.clone(),
+            max_downloads: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+    let public = db.get_public_share(&share.slug).unwrap().unwrap();
+    assert_eq!(public.kind, ShareKind::Bundle);
+    assert_eq!(public.file_size, 6);
+    let names: Vec<_> = public.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["a.txt", "b.txt"]);
+
+    // One file is a download, continuing it isn't
+    let member = db
+        .get_member_target(&share.slug, "b.txt", "", DownloadRequest::default())
+        .unwrap();
+    assert_eq!(member.size_bytes, 2);
+    assert!(member.counted);
+    interrupted(&db, &member, 1);
+    let rest = DownloadRequest {
+        offset: 1,
+        ..resume(&member.session)
+    };
+    let resumed = db
+        .get_member_target(&share.slug, "b.txt", "", rest)
+        .unwrap();
+    assert!(!resumed.counted);
+    assert!(matches!(
+        db.get_member_target(&share.slug, "c.txt", "", resume(&member.session)),
+        Err(ServiceError::NotFound)
+    ));
+    // The archive has no ranges, it's always a new download
+    assert!(matches!(
+        db.get_download_target(&share.slug, "", resume(&member.session)),
+        Err(ServiceError::LimitReached)
+    ));
+    let all = db
+        .create_share(&CreateShareReq {
+            abs_paths: paths.clone(),
+            ..Default::default()
+        })
+        .and_then(|s| db.get_download_target(&s.slug, "", DownloadRequest::default()))
+        .unwrap();
+    assert_eq!(all.kind, ShareKind::Bundle);
+    assert_eq!(all.members.len(), 2);
+
+    // Names have to be unique and members have to be files
+    let same_name = td.path().join("nested");
+    std::fs::create_dir(&same_name).unwrap();
+    std::fs::write(same_name.join("a.txt"), b"x").unwrap();
+    for bad in [
+        vec![
+            paths[0].clone(),
+            same_name.join("a.txt").to_str().unwrap().into(),
+        ],
+        vec![paths[0].clone(), same_name.to_str().unwrap().into()],
+    ] {
+        assert!(matches!(
+            db.create_share(&CreateShareReq {
+                abs_paths: bad,
+                ..Default::default()
+            }),
+            Err(ServiceError::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn bundle_files_count_once_per_session() {
+    let db = temp_db();
+    let td = tempfile::tempdir().unwrap();
+    let paths: Vec<String> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| {
+            let path = td.path().join(name);
+            std::fs::write(&path, b"abc").unwrap();
+            path.to_str().unwrap().to_string()
+        })
+        .collect();
+    let share = db
+        .create_share(&CreateShareReq {
+            abs_paths: paths,
+            max_downloads: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+    let first = db
+        .get_member_target(&share.slug, "a.txt", "", DownloadRequest::default())
+        .unwrap();
+    assert!(first.counted);
+    let same_session = DownloadRequest {
+        offset: 0,
+        ..resume(&first.session)
+    };
+    for name in ["b.txt", "c.txt"] {
+        let next = db
+            .get_member_target(&share.slug, name, "", same_session)
+            .unwrap();
+        assert!(!next.counted);
+        assert_eq!(next.session, first.session);
+    }
+    assert_eq!(db.get_share(&share.slug).unwrap().unwrap().dl_count, 1);
+
+    // Each file once, a second copy is another download
+    assert!(matches!(
+        db.get_member_target(&share.slug, "a.txt", "", same_session),
+        Err(ServiceError::LimitReached)
+    ));
+}
+
+#[test]
+fn download_events_filter_and_page() {
+    let db = temp_db();
+    for (slug, outcome) in [
+        ("aaa", DownloadOutcome::Success),
+        ("bbb", DownloadOutcome::BadPassword),
+        ("aaa", DownloadOutcome::Aborted),
+    ] {
+        db.record_download_event(&NewDownloadEvent {
+            slug: slug.into(),
+            file_id: Some(format!("file-{slug}")),
+            ip: Some("203.0.113.7".into()),
+            bytes_sent: 10,
+            outcome: Some(outcome),
+            ..NewDownloadEvent::default()
+        })
+        .unwrap();
+    }
+
+    let all = db
+        .list_download_events(&DownloadEventQuery::default())
+        .unwrap();
+    assert_eq!(all.total, 3);
+    assert_eq!(all.events[0].outcome, DownloadOutcome::Aborted);
+
+    let page = db
+        .list_download_events(&DownloadEventQuery {
+            slug: Some("aaa".into()),
+            limit: Some(1),
+            offset: Some(1),
+            ..DownloadEventQuery::default()
+        })
+        .unwrap();
+    assert_eq!(page.total, 2);
+    assert_eq!(page.events.len(), 1);
+    assert_eq!(page.events[0].outcome, DownloadOutcome::Success);
+
+    let by_file = db
+        .list_download_events(&DownloadEventQuery {
+            file_id: Some("file-bbb".into()),
+            ..DownloadEventQuery::default()
+        })
+        .unwrap();
+    assert_eq!(by_file.total, 1);
+    let future = db
+        .list_download_events(&DownloadEventQuery {
+            from: Some("2999-01-01".into()),
+            ..DownloadEventQuery::default()
+        })
+        .unwrap();
+    assert_eq!(future.total, 0);
+
+    for bad in [
+        DownloadEventQuery {
+            to: Some("yesterday-ish".into()),
+            ..DownloadEventQuery::default()
+        },
+        DownloadEventQuery {
+            limit: Some(0),
+            ..DownloadEventQuery::default()
+        },
+    ] {
+        assert!(matches!(
+            db.list_download_events(&bad),
+            Err(ServiceError::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn admin_audit_is_searchable_and_append_only() {
+    let td = tempfile::tempdir().unwrap();
+    let db_path = td.path().join("audit.db");
+    let db = Db::open(&db_path).unwrap();
+    for (actor, target, path) in [
+        ("alice", "aaa111", "/data/payroll.xlsx"),
+        ("bob", "bbb222", "/data/holiday.jpg"),
+    ] {
+        db.record_admin_audit(&NewAdminAudit {
+            actor: Some(actor.into()),
+            action: "POST /admin/share".into(),
+            target: Some(target.into()),
+            params: Some(serde_json::json!({ "abs_path": path })),
+            ip: Some("127.0.0.1".into()),
+            status: 200,
+        })
+        .unwrap();
+    }
+    db.record_admin_audit(&NewAdminAudit {
+        action: "POST /admin/login".into(),
+        status: 401,
+        ..NewAdminAudit::default()
+    })
+    .unwrap();
+
+    // Who shared payroll.xlsx?
+    let found = db
+        .list_admin_audit(&AdminAuditQuery {
+            action: Some("POST /admin/share".into()),
+            q: Some("payroll.xlsx".into()),
+            ..AdminAuditQuery::default()
+        })
+        .unwrap();
+    assert_eq!(found.total, 1);
+    assert_eq!(found.entries[0].actor.as_deref(), Some("alice"));
+    assert_eq!(
+        found.entries[0].params,
+        Some(serde_json::json!({ "abs_path": "/data/payroll.xlsx" }))
+    );
+
+    let all = db.list_admin_audit(&AdminAuditQuery::default()).unwrap();
+    assert_eq!(all.total, 3);
+    assert_eq!(all.entries[0].status, 401);
+    let by_bob = db
+        .list_admin_audit(&AdminAuditQuery {
+            actor: Some("bob".into()),
+            ..AdminAuditQuery::default()
+        })
+        .unwrap();
+    assert_eq!(by_bob.entries[0].target.as_deref(), Some("bbb222"));
+
+    let con = rusqlite::Connection::open(&db_path).unwrap();
+    assert!(con.execute("DELETE FROM admin_audit", []).is_err());
+    assert!(con
+        .execute("UPDATE admin_audit SET actor = 'mallory'", [])
+        .is_err());
+}
+
+#[test]
+fn update_share_applies_only_given_fields() {
+    let db = temp_db();
+    let (_td, p) = temp_file_with_size(10);
+    let share = db
+        .create_share(&CreateShareReq {
+            password: Some("secret".to_string()),
+            max_downloads: Some(2),
+            ..share_req(&p)
+        })
+        .unwrap();
+    let unlock = db.unlock_share(&share.slug, "secret").unwrap();
+    let target = db
+        .get_download_target(&share.slug, "secret", DownloadRequest::default())
+        .unwrap();
+    db.get_download_target(&share.slug, "secret", DownloadRequest::default())
+        .unwrap();
+
+    // Missing means unchanged, null clears
+    let changes: UpdateShareReq =
+        serde_json::from_str(r#"{"expires_at": "2999-01-01", "max_downloads": null}"#).unwrap();
+    assert_eq!(changes.password, None);
+    let updated = db.update_share(&share.slug, &changes).unwrap();
+    assert_eq!(updated.expires_at.as_deref(), Some("2999-01-01"));
+    assert_eq!(updated.max_downloads, None);
+    assert_eq!(updated.dl_count, 2);
+    assert!(updated.password_hash.is_some());
+
+    for (changes, what) in [
+        (
+            UpdateShareReq {
+                max_downloads: Some(Some(1)),
+                ..UpdateShareReq::default()
+            },
+            "below the 2 downloads",
+        ),
+        (
+            UpdateShareReq {
+                max_downloads: Some(Some(0)),
+                ..UpdateShareReq::default()
+            },
+            "at least 1",
+        ),
+        (
+            UpdateShareReq {
+                expires_at: Some(Some("soon".into())),
+                ..UpdateShareReq::default()
+            },
+            "not a date",
+        ),
+        (
+            UpdateShareReq {
+                password: Some(Some(String::new())),
+                ..UpdateShareReq::default()
+            },
+            "can't be empty",
+        ),
+        (UpdateShareReq::default(), "no changes"),
+    ] {
+        match db.update_share(&share.slug, &changes) {
+            Err(ServiceError::Invalid(msg)) => assert!(msg.contains(what), "{msg}"),
+            other => panic!("expected Invalid, got {other:?}"),
+        }
+    }
+    let reset = db
+        .update_share(
+            &share.slug,
+            &UpdateShareReq {
+                max_downloads: Some(Some(1)),
+                reset_dl_count: true,
+                ..UpdateShareReq::default()
+            },
+        )
+        .unwrap();
+    assert_eq!((reset.max_downloads, reset.dl_count), (Some(1), 0));
+
+    // A new password ends what the old one unlocked
+    db.update_share(
+        &share.slug,
+        &UpdateShareReq {
+            password: Some(Some("changed".into())),
+            ..UpdateShareReq::default()
+        },
+    )
+    .unwrap();
+    for credential in [Credential::Token(&unlock.token), Credential::from("secret")] {
+        assert!(matches!(
+            db.get_download_target(&share.slug, credential, DownloadRequest::default()),
+            Err(ServiceError::BadPassword)
+        ));
+    }
+    assert!(db
+        .get_download_target(&share.slug, Credential::None, resume(&target.session))
+        .is_err());
+
+    let open = db
+        .update_share(
+            &share.slug,
+            &serde_json::from_str(r#"{"password": null}"#).unwrap(),
+        )
+        .unwrap();
+    assert!(open.password_hash.is_none());
+    assert!(db
+        .get_download_target(&share.slug, Credential::None, DownloadRequest::default())
+        .is_ok());
+    assert!(matches!(
+        db.update_share(
+            "nope",
+            &serde_json::from_str(r#"{"reset_dl_count": true}"#).unwrap()
+        ),
+        Err(ServiceError::NotFound)
+    ));
+}
+
+#[test]
+fn custom_slugs_are_used_once() {
+    let db = temp_db().with_slug_alphabet(SlugAlphabet::Readable);
+    let (_td, p) = temp_file_with_size(10);
+    let custom = |slug: &str| CreateShareReq {
+        slug: Some(slug.to_string()),
+        ..share_req(&p)
+    };
+
+    let share = db.create_share(&custom("q3-report")).unwrap();
+    assert_eq!(share.slug, "q3-report");
+    assert!(matches!(
+        db.create_share(&custom("q3-report")),
+        Err(ServiceError::SlugTaken(slug)) if slug == "q3-report"
+    ));
+    // Case doesn't make a slug new
+    assert!(matches!(
+        db.create_share(&custom("Q3-Report")),
+        Err(ServiceError::SlugTaken(slug)) if slug == "Q3-Report"
+    ));
+    assert!(matches!(
+        db.create_share(&custom("admin")),
+        Err(ServiceError::Invalid(_))
+    ));
+
+    // Without one the configured alphabet is used
+    let generated = db.create_share(&share_req(&p)).unwrap();
+    assert_eq!(generated.slug.len(), 8);
+    assert!(!generated.slug.contains(['0', '1', 'o', 'l']));
 }
@@ -0,0 +1,141 @@
+use actix_web::http::header::{HeaderMap, HeaderValue, HttpDate, IfRange, X_FORWARDED_FOR};
+use actix_web::test::TestRequest;
+use file_serve::db::{DownloadEvent, DownloadOutcome};
+use file_serve::download::{entity_tag, events_csv, if_range_matches, range_start, TrustedProxies};
+use file_serve::hashing::{legacy_digest, repr_digest};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn if_range_etag_must_match_recorded_file() {
+    let current = entity_tag(1024, Some(1_700_000_000)).unwrap();
+    let changed = entity_tag(2048, Some(1_700_000_000)).unwrap();
+
+    assert!(if_range_matches(None, 1024, Some(1_700_000_000)));
+    assert!(if_range_matches(
+        Some(&IfRange::EntityTag(current)),
+        1024,
+        Some(1_700_000_000)
+    ));
+    assert!(!if_range_matches(
+        Some(&IfRange::EntityTag(changed)),
+        1024,
+        Some(1_700_000_000)
+    ));
+    // No recorded mtime means nothing to validate against
+    assert!(entity_tag(1024, None).is_none());
+}
+
+#[test]
+fn if_range_date_must_equal_recorded_mtime() {
+    let at = |secs| HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs));
+
+    assert!(if_range_matches(
+        Some(&IfRange::Date(at(1_700_000_000))),
+        10,
+        Some(1_700_000_000)
+    ));
+    assert!(!if_range_matches(
+        Some(&IfRange::Date(at(1_600_000_000))),
+        10,
+        Some(1_700_000_000)
+    ));
+    assert!(!if_range_matches(
+        Some(&IfRange::Date(at(1_700_000_000))),
+        10,
+        None
+    ));
+}
+
+#[test]
+fn range_start_finds_the_first_byte_asked_for() {
+    for (range, start) in [
+        (None, 0),
+        (Some("bytes=0-"), 0),
+        (Some("bytes=0-99"), 0),
+        (Some("bytes=500-"), 500),
+        (Some("bytes=500-599, 100-199"), 100),
+        (Some("bytes=-100"), u64::MAX),
+        (Some("bytes=100-199, -50"), 100),
+        // Not a byte range we understand, treated as the whole file
+        (Some("items=5-"), 0),
+        (Some("bytes=x-"), 0),
+    ] {
+        let mut req = TestRequest::default();
+        if let Some(range) = range {
+            req = req.insert_header(("range", range));
+        }
+        assert_eq!(range_start(&req.to_http_request()), start, "{range:?}");
+    }
+}
+
+#[test]
+fn digest_headers_encode_raw_bytes() {
+    let hex = "01d448afd928065458cf670b60f5a594d735af0172c8d67f22a81680132681ca";
+    assert_eq!(
+        repr_digest(hex).unwrap(),
+        "sha-256=:AdRIr9koBlRYz2cLYPWllNc1rwFyyNZ/IqgWgBMmgco=:"
+    );
+    assert_eq!(
+        legacy_digest(hex).unwrap(),
+        "sha-256=AdRIr9koBlRYz2cLYPWllNc1rwFyyNZ/IqgWgBMmgco="
+    );
+    assert!(repr_digest("not hex").is_none());
+}
+
+#[test]
+fn forwarded_for_is_only_believed_from_trusted_proxies() {
+    let proxies = TrustedProxies::new(&["10.0.0.0/8".into(), "::1".into()]).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        X_FORWARDED_FOR,
+        HeaderValue::from_static("6.6.6.6, 203.0.113.7, 10.1.2.3"),
+    );
+    let ip = |s: &str| s.parse().unwrap();
+
+    // The spoofable left end is never reached
+    assert_eq!(
+        proxies.client_ip(ip("10.0.0.1"), &headers),
+        ip("203.0.113.7")
+    );
+    assert_eq!(proxies.client_ip(ip("::1"), &headers), ip("203.0.113.7"));
+    assert_eq!(
+        proxies.client_ip(ip("198.51.100.1"), &headers),
+        ip("198.51.100.1")
+    );
+    assert_eq!(
+        proxies.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+        ip("10.0.0.1")
+    );
+    assert!(proxies.trusts(ip("::ffff:10.9.9.9")));
+    assert!(!proxies.trusts(ip("11.0.0.1")));
+
+    assert!(TrustedProxies::new(&["10.0.0.0/33".into()]).is_err());
+    assert!(TrustedProxies::new(&["proxy.local".into()]).is_err());
+}
+
+#[test]
+fn events_csv_quotes_and_defuses_fields() {
+    let event = DownloadEvent {
+        id: 1,
+        slug: "abc123".into(),
+        file_id: None,
+        at: "2024-05-01 12:00:00".into(),
+        ip: Some("203.0.113.7".into()),
+        user_agent: Some("=HYPERLINK(\"x\"), curl".into()),
+        bytes_sent: 42,
+        range: Some("bytes=0-9".into()),
+        outcome: DownloadOutcome::Success,
+    };
+    let csv = events_csv(&[event]);
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("id,slug,file_id,at,ip,user_agent,bytes_sent,range,outcome")
+    );
+    assert_eq!(
+        lines.next(),
+        Some(
+            "1,abc123,,2024-05-01 12:00:00,203.0.113.7,\"'=HYPERLINK(\"\"x\"\"), curl\",42,bytes=0-9,success"
+        )
+    );
+}
@@ -0,0 +1,135 @@
+use file_serve::db::{CreateShareReq, Db, DownloadRequest};
+use file_serve::janitor::{Janitor, JanitorSettings};
+use file_serve::policy::{ExpiredSharePolicy, PathPolicy};
+use file_serve::storage::Storage;
+
+fn share_req(abs_path: &str) -> CreateShareReq {
+    CreateShareReq {
+        abs_path: abs_path.to_string(),
+        ..CreateShareReq::default()
+    }
+}
+
+fn upload(storage: &Storage, name: &str) -> String {
+    let mut partial = storage.begin().unwrap();
+    partial.write(b"hello").unwrap();
+    let stored = storage.persist(partial, name).unwrap();
+    stored.to_string_lossy().into_owned()
+}
+
+#[test]
+fn dead_shares_are_archived_or_deleted() {
+    let td = tempfile::tempdir().unwrap();
+    let storage = Storage::new(td.path(), 1024).unwrap();
+    let db = Db::new_in_memory()
+        .unwrap()
+        .with_path_policy(PathPolicy::new(&[td.path()], &[]).unwrap());
+    let path = upload(&storage, "a.txt");
+
+    let expired = db
+        .create_share(&CreateShareReq {
+            expires_at: Some("2000-01-01 00:00:00".into()),
+            ..share_req(&path)
+        })
+        .unwrap();
+    let used_up = db
+        .create_share(&CreateShareReq {
+            max_downloads: Some(1),
+            ..share_req(&path)
+        })
+        .unwrap();
+    let live = db.create_share(&share_req(&path)).unwrap();
+    // The last download may still be resuming
+    let target = db
+        .get_download_target(&used_up.slug, "", DownloadRequest::default())
+        .unwrap();
+
+    db.return_download_bytes(&target.session, &target.abs_path, target.reserved - 1)
+        .unwrap();
+
+    let janitor = Janitor::new(db.clone(), storage.clone(), JanitorSettings::default());
+    let report = janitor.run_once(false).unwrap();
+    assert_eq!(report.dead_shares, 1);
+    assert!(db.get_share(&expired.slug).unwrap().is_none());
+    assert!(db.get_share(&used_up.slug).unwrap().is_some());
+    assert!(db.get_share(&live.slug).unwrap().is_some());
+    assert!(db
+        .get_download_target(
+            &used_up.slug,
+            "",
+            DownloadRequest {
+                session: Some(&target.session),
+                offset: 1,
+            }
+        )
+        .is_ok());
+
+    let archived = db.list_archived_shares().unwrap();
+    assert_eq!(archived.len(), 1);
+    assert_eq!(archived[0].slug, expired.slug);
+    assert_eq!(archived[0].reason, "expired");
+    assert_eq!(archived[0].abs_path, path);
+
+    // Without archiving nothing is kept
+    let janitor = Janitor::new(
+        db.clone(),
+        storage,
+        JanitorSettings {
+            expired_shares: ExpiredSharePolicy::Delete,
+            ..JanitorSettings::default()
+        },
+    );
+    db.create_share(&CreateShareReq {
+        expires_at: Some("2000-01-01 00:00:00".into()),
+        ..share_req(&path)
+    })
+    .unwrap();
+    assert_eq!(janitor.run_once(true).unwrap().dead_shares, 1);
+    assert_eq!(db.list_archived_shares().unwrap().len(), 1);
+}
+
+#[test]
+fn orphan_files_go_after_the_grace_period() {
+    let td = tempfile::tempdir().unwrap();
+    let storage = Storage::new(&td.path().join("uploads"), 1024).unwrap();
+    let db = Db::new_in_memory()
+        .unwrap()
+        .with_path_policy(PathPolicy::new(&[td.path()], &[]).unwrap());
+
+    let shared = upload(&storage, "shared.txt");
+    let orphan = upload(&storage, "orphan.txt");
+    let outside = td.path().join("outside.txt");
+    std::fs::write(&outside, b"keep").unwrap();
+    let outside = outside.to_string_lossy().into_owned();
+
+    db.create_share(&share_req(&shared)).unwrap();
+    let orphan_file = db.create_or_get_file(&orphan).unwrap();
+    let outside_file = db.create_or_get_file(&outside).unwrap();
+
+    let settings = JanitorSettings {
+        orphan_grace_secs: 3600,
+        purge_orphan_uploads: true,
+        ..JanitorSettings::default()
+    };
+    let report = Janitor::new(db.clone(), storage.clone(), settings)
+        .run_once(false)
+        .unwrap();
+    assert_eq!(report.orphan_files, 0);
+    assert!(db.get_file(&orphan_file.id).unwrap().is_some());
+
+    let settings = JanitorSettings {
+        orphan_grace_secs: 0,
+        ..settings
+    };
+    let report = Janitor::new(db.clone(), storage, settings)
+        .run_once(false)
+        .unwrap();
+    assert_eq!(report.orphan_files, 2);
+    assert_eq!(report.deleted_uploads, 1);
+    assert!(db.get_file(&orphan_file.id).unwrap().is_none());
+    assert!(db.get_file(&outside_file.id).unwrap().is_none());
+    // Only managed uploads are deleted from disk
+    assert!(!std::path::Path::new(&orphan).exists());
+    assert!(std::path::Path::new(&outside).exists());
+    assert!(std::path::Path::new(&shared).exists());
+}
@@ -0,0 +1,65 @@
+use file_serve::db::Db;
+use file_serve::error::ServiceError;
+use file_serve::migrations::{self, LATEST_VERSION};
+use rusqlite::{params, Connection};
+
+#[test]
+fn v1_db_is_upgraded_with_data_intact() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("old.db");
+
+    {
+        let mut con = Connection::open(&db_path).unwrap();
+        migrations::migrate_to(&mut con, 1).unwrap();
+        assert_eq!(migrations::version(&con).unwrap(), 1);
+        con.execute(
+            "INSERT INTO file (id, abs_path, name, size_bytes) VALUES ('f1', '/srv/a.bin', 'a.bin', 3)",
+            [],
+        )
+        .unwrap();
+        con.execute(
+            "INSERT INTO share (slug, file_id) VALUES ('old-slug', 'f1')",
+            params![],
+        )
+        .unwrap();
+    }
+
+    let db = Db::open(&db_path).unwrap();
+    let share = db.get_share("old-slug").unwrap().unwrap();
+    assert_eq!(share.file_id, "f1");
+    // Tables from later versions are usable
+    assert!(!db.has_admin().unwrap());
+
+    let con = Connection::open(&db_path).unwrap();
+    assert_eq!(migrations::version(&con).unwrap(), LATEST_VERSION);
+}
+
+#[test]
+fn pre_migration_db_is_adopted() {
+    // DBs from before user_version was tracked already have the v1 tables
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("legacy.db");
+    Connection::open(&db_path)
+        .unwrap()
+        .execute_batch(include_str!("../migrations/0001_init.sql"))
+        .unwrap();
+
+    Db::open(&db_path).unwrap();
+    let con = Connection::open(&db_path).unwrap();
+    assert_eq!(migrations::version(&con).unwrap(), LATEST_VERSION);
+}
+
+#[test]
+fn newer_db_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("future.db");
+    Connection::open(&db_path)
+        .unwrap()
+        .pragma_update(None, "user_version", LATEST_VERSION + 1)
+        .unwrap();
+
+    assert!(matches!(
+        Db::open(&db_path),
+        Err(ServiceError::SchemaTooNew { .. })
+    ));
+}
@@ -0,0 +1,64 @@
+use file_serve::db::Db;
+use file_serve::error::ServiceError;
+use file_serve::signing::{UrlSignature, UrlSigner, MAX_SIGNED_URL_SECS};
+
+fn signer(db: &Db) -> UrlSigner {
+    UrlSigner::new(db.signing_keys().unwrap())
+}
+
+#[test]
+fn signed_url_verifies_only_for_its_slug() {
+    let db = Db::new_in_memory().unwrap();
+    db.rotate_signing_key().unwrap();
+    let signer = signer(&db);
+
+    let sig = signer.sign("abc123", 60).unwrap();
+    signer.verify("abc123", &sig).unwrap();
+    assert!(matches!(
+        signer.verify("abc124", &sig),
+        Err(ServiceError::BadSignature(_))
+    ));
+    // Pushing the expiry out breaks the signature
+    let extended = UrlSignature {
+        expires: sig.expires + 3600,
+        ..sig.clone()
+    };
+    assert!(matches!(
+        signer.verify("abc123", &extended),
+        Err(ServiceError::BadSignature("signature does not match"))
+    ));
+    let stale = UrlSignature { expires: 1, ..sig };
+    assert!(matches!(
+        signer.verify("abc123", &stale),
+        Err(ServiceError::BadSignature("link has expired"))
+    ));
+
+    assert!(signer.sign("abc123", 0).is_err());
+    assert!(signer.sign("abc123", MAX_SIGNED_URL_SECS + 1).is_err());
+    assert!(UrlSigner::default().sign("abc123", 60).is_err());
+}
+
+#[test]
+fn rotated_keys_verify_until_deleted() {
+    let db = Db::new_in_memory().unwrap();
+    let first = db.rotate_signing_key().unwrap();
+    let signer = signer(&db);
+    let old = signer.sign("abc123", 60).unwrap();
+    assert_eq!(old.kid, first.id);
+
+    let second = db.rotate_signing_key().unwrap();
+    signer.set_keys(db.signing_keys().unwrap());
+    let new = signer.sign("abc123", 60).unwrap();
+    assert_eq!(new.kid, second.id);
+    signer.verify("abc123", &old).unwrap();
+
+    // The key in use can't go, older ones can
+    assert!(db.delete_signing_key(second.id).is_err());
+    assert!(db.delete_signing_key(first.id).unwrap());
+    signer.set_keys(db.signing_keys().unwrap());
+    assert!(matches!(
+        signer.verify("abc123", &old),
+        Err(ServiceError::BadSignature("unknown signing key"))
+    ));
+    signer.verify("abc123", &new).unwrap();
+}
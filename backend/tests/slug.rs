@@ -0,0 +1,48 @@
+use file_serve::error::ServiceError;
+use file_serve::slug::{validate_custom, SlugAlphabet};
+
+#[test]
+fn alphabets_generate_their_own_kind_of_slug() {
+    let slug = SlugAlphabet::Alphanumeric.generate(12);
+    assert_eq!(slug.len(), 12);
+    assert!(slug.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    for _ in 0..50 {
+        let slug = SlugAlphabet::Readable.generate(10);
+        assert_eq!(slug.len(), 10);
+        assert!(slug
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+        assert!(!slug.contains(['0', '1', 'o', 'l']), "{slug}");
+    }
+
+    let words = SlugAlphabet::Words.generate(8);
+    let parts: Vec<&str> = words.split('-').collect();
+    assert_eq!(parts.len(), 3, "{words}");
+    assert!((10..100).contains(&parts[2].parse::<u32>().unwrap()));
+    // Generated slugs would pass as custom ones too
+    validate_custom(&words).unwrap();
+}
+
+#[test]
+fn custom_slugs_are_checked() {
+    for ok in ["q3-report", "Q3_2024", "abc", "brave-otter-42"] {
+        validate_custom(ok).unwrap();
+    }
+    for (bad, why) in [
+        ("ab", "characters long"),
+        (&"x".repeat(65), "characters long"),
+        ("q3 report", "contains ' '"),
+        ("über", "contains 'ü'"),
+        ("../etc", "contains '.'"),
+        ("-start", "start and end"),
+        ("end_", "start and end"),
+        ("Admin", "reserved"),
+        ("api", "reserved"),
+    ] {
+        match validate_custom(bad) {
+            Err(ServiceError::Invalid(msg)) => assert!(msg.contains(why), "{bad}: {msg}"),
+            other => panic!("{bad} should be invalid, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use file_serve::error::ServiceError;
+use file_serve::storage::{sanitize_name, Storage};
+
+#[test]
+fn names_keep_only_the_last_component() {
+    assert_eq!(sanitize_name("report.pdf").as_deref(), Some("report.pdf"));
+    assert_eq!(sanitize_name("../../etc/passwd").as_deref(), Some("passwd"));
+    assert_eq!(
+        sanitize_name(r"C:\Users\me\a.txt").as_deref(),
+        Some("a.txt")
+    );
+    assert_eq!(sanitize_name(".."), None);
+    assert_eq!(sanitize_name("dir/"), None);
+    assert_eq!(sanitize_name("a\nb"), None);
+}
+
+#[test]
+fn persisted_upload_lands_in_storage_dir() {
+    let td = tempfile::tempdir().unwrap();
+    let storage = Storage::new(&td.path().join("uploads"), 1024).unwrap();
+
+    let mut partial = storage.begin().unwrap();
+    partial.write(b"hello ").unwrap();
+    partial.write(b"world").unwrap();
+    assert_eq!(partial.written(), 11);
+    let stored = storage.persist(partial, "greeting.txt").unwrap();
+
+    assert!(stored.starts_with(storage.dir()));
+    assert_eq!(stored.file_name().unwrap(), "greeting.txt");
+    assert_eq!(std::fs::read(&stored).unwrap(), b"hello world");
+
+    storage.discard(&stored);
+    assert!(!stored.exists());
+}
+
+#[test]
+fn oversized_or_abandoned_uploads_leave_nothing_behind() {
+    let td = tempfile::tempdir().unwrap();
+    let storage = Storage::new(td.path(), 8).unwrap();
+    let partial_dir = storage.dir().join(".partial");
+
+    let mut partial = storage.begin().unwrap();
+    partial.write(b"12345").unwrap();
+    assert!(matches!(
+        partial.write(b"6789"),
+        Err(ServiceError::TooLarge { limit: 8 })
+    ));
+    drop(partial);
+
+    assert_eq!(std::fs::read_dir(&partial_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn session_resumes_at_recorded_offset() {
+    let td = tempfile::tempdir().unwrap();
+    let storage = Storage::new(td.path(), 1024).unwrap();
+    storage.create_session("s1").unwrap();
+
+    let mut partial = storage.resume_session("s1", 0, 10).unwrap();
+    // Only one writer per session
+    assert!(matches!(
+        storage.resume_session("s1", 0, 10),
+        Err(ServiceError::UploadConflict(_))
+    ));
+    partial.write(b"abcdef").unwrap();
+    drop(partial);
+
+    // Only 4 bytes were recorded, the rest gets overwritten
+    let mut partial = storage.resume_session("s1", 4, 10).unwrap();
+    partial.write(b"EFGHIJ").unwrap();
+    assert!(partial.write(b"K").is_err());
+    let stored = storage.persist(partial, "letters.txt").unwrap();
+    assert_eq!(std::fs::read(&stored).unwrap(), b"abcdEFGHIJ");
+
+    storage.create_session("s2").unwrap();
+    storage.create_session("s3").unwrap();
+    assert_eq!(storage.sweep_partials(&["s2".to_string()]).unwrap(), 1);
+    assert!(storage.resume_session("s2", 0, 1).is_ok());
+    assert!(storage.resume_session("s3", 0, 1).is_err());
+}
@@ -0,0 +1,128 @@
+use actix_web::body::{self, BodyStream, BoxBody, MessageBody};
+use actix_web::web::Bytes;
+use file_serve::db::{CreateShareReq, Db, Share};
+use file_serve::error::ServiceError;
+use file_serve::policy::PathPolicy;
+use file_serve::throttle::{Throttle, ThrottleLimits, Throttled};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+fn temp_file() -> tempfile::NamedTempFile {
+    let mut f = tempfile::NamedTempFile::new().unwrap();
+    f.write_all(b"hello").unwrap();
+    f
+}
+
+fn share(
+    db: &Db,
+    file: &tempfile::NamedTempFile,
+    max_concurrent: Option<i64>,
+    bytes_per_sec: Option<i64>,
+) -> Share {
+    db.create_share(&CreateShareReq {
+        abs_path: file.path().to_string_lossy().into_owned(),
+        password: None,
+        expires_at: None,
+        max_downloads: None,
+        abs_paths: Vec::new(),
+        slug: None,
+        max_concurrent,
+        bytes_per_sec,
+    })
+    .unwrap()
+}
+
+#[test]
+fn request_rate_is_counted_per_ip() {
+    let throttle = Throttle::new(ThrottleLimits {
+        requests_per_minute: Some(3),
+        ..ThrottleLimits::default()
+    });
+
+    for _ in 0..3 {
+        throttle.check_rate("10.0.0.1").unwrap();
+    }
+    assert!(matches!(
+        throttle.check_rate("10.0.0.1"),
+        Err(ServiceError::RateLimited { retry_after, .. }) if (1..=20).contains(&retry_after)
+    ));
+    throttle.check_rate("10.0.0.2").unwrap();
+
+    // Without a limit nothing is counted
+    let open = Throttle::default();
+    for _ in 0..100 {
+        open.check_rate("10.0.0.1").unwrap();
+    }
+}
+
+#[test]
+fn permits_cap_concurrent_downloads() {
+    let db = Db::new_in_memory()
+        .unwrap()
+        .with_path_policy(PathPolicy::new(&[std::env::temp_dir()], &[]).unwrap());
+    let file = temp_file();
+    let a = share(&db, &file, None, None);
+    let b = share(&db, &file, Some(1), None);
+    let throttle = Throttle::new(ThrottleLimits {
+        per_ip: Some(2),
+        per_share: Some(3),
+        ..ThrottleLimits::default()
+    });
+
+    let first = throttle.acquire(Some("10.0.0.1"), &a).unwrap();
+    let _second = throttle.acquire(Some("10.0.0.1"), &a).unwrap();
+    assert!(matches!(
+        throttle.acquire(Some("10.0.0.1"), &a),
+        Err(ServiceError::RateLimited { .. })
+    ));
+    let _third = throttle.acquire(Some("10.0.0.2"), &a).unwrap();
+    // Server-wide per share limit
+    assert!(matches!(
+        throttle.acquire(Some("10.0.0.3"), &a),
+        Err(ServiceError::RateLimited { .. })
+    ));
+    drop(first);
+    throttle.acquire(Some("10.0.0.1"), &a).unwrap();
+
+    // The share's own limit wins
+    let held = throttle.acquire(None, &b).unwrap();
+    assert!(throttle.acquire(Some("10.0.0.4"), &b).is_err());
+    drop(held);
+    throttle.acquire(Some("10.0.0.4"), &b).unwrap();
+}
+
+#[actix_web::test]
+async fn throttled_body_is_paced_but_complete() {
+    let db = Db::new_in_memory()
+        .unwrap()
+        .with_path_policy(PathPolicy::new(&[std::env::temp_dir()], &[]).unwrap());
+    let file = temp_file();
+    let capped = share(&db, &file, None, Some(64 * 1024));
+    let throttle = Throttle::default();
+
+    let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(Bytes::from(vec![7u8; 32 * 1024])));
+    let stream = BodyStream::new(futures_util::stream::iter(chunks));
+    let body = Throttled::new(
+        BoxBody::new(stream),
+        throttle.acquire(None, &capped).unwrap(),
+    );
+
+    let start = Instant::now();
+    let bytes = body::to_bytes(body).await.unwrap();
+    // The first second's worth is free, the rest waits its turn
+    assert_eq!(bytes.len(), 128 * 1024);
+    assert!(start.elapsed() >= Duration::from_millis(900));
+
+    // The permit went with the body
+    let single = Throttle::new(ThrottleLimits {
+        per_share: Some(1),
+        ..ThrottleLimits::default()
+    });
+    let body = Throttled::new(
+        Bytes::from_static(b"x").boxed(),
+        single.acquire(None, &capped).unwrap(),
+    );
+    assert!(single.acquire(None, &capped).is_err());
+    body::to_bytes(body).await.unwrap();
+    single.acquire(None, &capped).unwrap();
+}
@@ -1,13 +1,43 @@
 export async function fetchPublicShare(slug) {
     const res = await fetch(`/api/share/${encodeURIComponent(slug)}`);
     if (res.status === 404) return null;
-    if (!res.ok) throw new Error(`server error: ${res.status}`);
+    if (!res.ok) {
+        // Backend errors are JSON: { error, message }
+        const body = await res.json().catch(() => null);
+        throw new Error(body?.message ?? `server error: ${res.status}`);
+    }
     return res.json();
 }
 
-export function buildDownloadUrl(slug, password) {
-    const base = `/api/download/${encodeURIComponent(slug)}`;
-    if (!password) return base;
-    const params = new URLSearchParams({ password });
-    return `${base}?${params.toString()}`;
+// Trades the password for an HttpOnly cookie the download and tree
+// routes of this share accept, so it never ends up in a URL
+export async function unlockShare(slug, password) {
+    const res = await fetch(`/api/share/${encodeURIComponent(slug)}/unlock`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ password }),
+    });
+    if (!res.ok) {
+        const body = await res.json().catch(() => null);
+        throw new Error(body?.message ?? `server error: ${res.status}`);
+    }
+    return res.json();
+}
+
+export async function fetchShareTree(slug, path) {
+    const params = new URLSearchParams({ path: path ?? '' });
+    const res = await fetch(`/api/share/${encodeURIComponent(slug)}/tree?${params.toString()}`);
+    if (!res.ok) {
+        const body = await res.json().catch(() => null);
+        throw new Error(body?.message ?? `server error: ${res.status}`);
+    }
+    return res.json();
+}
+
+// Password protected shares need `unlockShare` first
+export function buildDownloadUrl(slug, name) {
+    let base = `/api/download/${encodeURIComponent(slug)}`;
+    // One file of a bundle, or a path inside a directory share
+    if (name) base += `/${name.split('/').map(encodeURIComponent).join('/')}`;
+    return base;
 }
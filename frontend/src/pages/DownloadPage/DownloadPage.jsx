@@ -1,6 +1,6 @@
 import { useEffect, useState } from 'react';
 import { useParams, useNavigate } from 'react-router-dom';
-import { fetchPublicShare, buildDownloadUrl } from '../api';
+import { fetchPublicShare, fetchShareTree, unlockShare, buildDownloadUrl } from '../api';
 import NotFound from './NotFound.jsx';
 
 function formatBytes(n) {
@@ -17,8 +17,11 @@ export default function DownloadPage() {
 
     const [info, setInfo] = useState(null);
     const [password, setPassword] = useState('');
+    const [unlockErr, setUnlockErr] = useState(null);
     const [loading, setLoading] = useState(true);
     const [err, setErr] = useState(null);
+    const [tree, setTree] = useState(null);
+    const [treeErr, setTreeErr] = useState(null);
 
     useEffect(() => {
         (async () => {
@@ -41,11 +44,34 @@ export default function DownloadPage() {
         return () => { };
     }, [slug, nav]);
 
-    function handleDownload() {
-        // No separate password check needed: backend accepts ?password=
-        const url = buildDownloadUrl(slug, info?.password_required ? password : undefined);
+    // Sets the unlock cookie; false (with the reason shown) if the password was refused
+    async function unlock() {
+        if (!info?.password_required) return true;
+        try {
+            setUnlockErr(null);
+            await unlockShare(slug, password);
+            return true;
+        } catch (e) {
+            setUnlockErr(`${e.message ?? e}`);
+            return false;
+        }
+    }
+
+    async function handleDownload(name) {
+        if (!await unlock()) return;
         // Navigate to the file URL; browser handles the download
-        window.location.href = url;
+        window.location.href = buildDownloadUrl(slug, name);
+    }
+
+    async function browse(path) {
+        // Only the first listing needs the password, the cookie covers the rest
+        if (!tree && !await unlock()) return;
+        try {
+            setTreeErr(null);
+            setTree(await fetchShareTree(slug, path));
+        } catch (e) {
+            setTreeErr(`${e.message ?? e}`);
+        }
     }
 
     if (loading) return <div style={{ padding: 20 }}>Loading…</div>;
@@ -57,9 +83,12 @@ export default function DownloadPage() {
         <div style={{ padding: 20, maxWidth: 720 }}>
             <h1>{info.file_name}</h1>
             <p>Size: {formatBytes(info.file_size)}</p>
+            {info.kind === 'dir' && <p>Folder with {info.entry_count} entries, downloaded as a ZIP archive</p>}
+            {info.kind === 'bundle' && <p>{info.entry_count} files, downloaded together as a ZIP archive</p>}
             <p>Downloads: {info.dl_count}</p>
             {info.max_downloads != null && <p>Max downloads: {info.max_downloads}</p>}
             {info.expires_at && <p>Expires: {info.expires_at}</p>}
+            {info.sha256 && <p>SHA-256: <code style={{ wordBreak: 'break-all' }}>{info.sha256}</code></p>}
 
             {info.password_required && (
                 <div style={{ margin: '12px 0' }}>
@@ -70,10 +99,51 @@ export default function DownloadPage() {
                         onChange={e => setPassword(e.target.value)}
                         placeholder="••••••••"
                     />
+                    {unlockErr && <p style={{ color: 'crimson' }}>{unlockErr}</p>}
                 </div>
             )}
 
-            <button onClick={handleDownload}>Download</button>
+            {info.files && (
+                <ul>
+                    {info.files.map(f => (
+                        <li key={f.name}>
+                            {f.name} ({formatBytes(f.size_bytes)}){' '}
+                            <button onClick={() => handleDownload(f.name)}>Download</button>
+                        </li>
+                    ))}
+                </ul>
+            )}
+
+            <button onClick={() => handleDownload()}>{info.files ? 'Download all' : 'Download'}</button>
+            {info.kind === 'dir' && !tree && (
+                <button style={{ marginLeft: 8 }} onClick={() => browse('')}>Browse files</button>
+            )}
+
+            {treeErr && <p style={{ color: 'crimson' }}>{treeErr}</p>}
+            {tree && (
+                <div style={{ marginTop: 16 }}>
+                    <h2>/{tree.path}</h2>
+                    <ul>
+                        {tree.path && (
+                            <li>
+                                <a href="#" onClick={e => { e.preventDefault(); browse(tree.path.split('/').slice(0, -1).join('/')); }}>..</a>
+                            </li>
+                        )}
+                        {tree.entries.map(entry => (
+                            <li key={entry.path}>
+                                {entry.kind === 'dir' ? (
+                                    <a href="#" onClick={e => { e.preventDefault(); browse(entry.path); }}>{entry.name}/</a>
+                                ) : (
+                                    <>
+                                        {entry.name} ({formatBytes(entry.size_bytes)}){' '}
+                                        <button onClick={() => handleDownload(entry.path)}>Download</button>
+                                    </>
+                                )}
+                            </li>
+                        ))}
+                    </ul>
+                </div>
+            )}
         </div>
     );
 }